@@ -0,0 +1,298 @@
+use crate::graph::{FilterChain, FilterGraph, FilterNode};
+use crate::scale::floor_even;
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// Strength of the blurred background for `--aspect-mode pad`.
+const BLUR_RADIUS: u32 = 20;
+const BLUR_POWER: u32 = 5;
+
+/// How `--aspect` reframes a picture of another shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AspectMode {
+    /// Fit the whole picture on a blurred, enlarged copy of itself.
+    #[default]
+    Pad,
+    /// Cut the middle out.
+    CropCenter,
+    /// Cut out the window at --crop-offset.
+    CropOffset,
+}
+
+/// What the reframing does to a known frame size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reframe {
+    Crop {
+        width: u32,
+        height: u32,
+    },
+    /// Picture fitted and centred on a `width`x`height` canvas.
+    BlurPad {
+        width: u32,
+        height: u32,
+    },
+}
+
+/// `--aspect` options, and after probing the reframing they come to.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AspectSettings {
+    /// Target aspect ratio, e.g. `(9, 16)`.
+    pub ratio: Option<(u32, u32)>,
+    pub mode: AspectMode,
+    /// Crop window position from 0 (left/top) to 1 (right/bottom).
+    pub offset: f64,
+    pub reframe: Option<Reframe>,
+}
+
+impl AspectSettings {
+    /// Predicts the reframing of a `width`x`height` picture (after scaling)
+    /// the way ffmpeg evaluates `graph` on it. Pictures already within a
+    /// pixel of the ratio are left alone.
+    pub fn resolve(&mut self, width: u32, height: u32) {
+        self.reframe = None;
+        let Some((rw, rh)) = self.ratio else {
+            return;
+        };
+        if width == 0 || height == 0 {
+            return;
+        }
+        let (w, h) = (width as f64, height as f64);
+        let (ratio_w, ratio_h) = (rw as f64, rh as f64);
+        // Largest window of the target shape inside the picture.
+        let (cw, ch) = (
+            floor_even(w.min(h * ratio_w / ratio_h)),
+            floor_even(h.min(w * ratio_h / ratio_w)),
+        );
+        if width.abs_diff(cw) <= 1 && height.abs_diff(ch) <= 1 {
+            return;
+        }
+        self.reframe = Some(match self.mode {
+            AspectMode::CropCenter | AspectMode::CropOffset => Reframe::Crop {
+                width: cw,
+                height: ch,
+            },
+            AspectMode::Pad => {
+                let short = w.min(h);
+                let (canvas_w, canvas_h) = if rw < rh {
+                    (short, short * ratio_h / ratio_w)
+                } else {
+                    (short * ratio_w / ratio_h, short)
+                };
+                let (width, height) =
+                    if u64::from(width) * u64::from(rh) > u64::from(height) * u64::from(rw) {
+                        let height = floor_even(canvas_h);
+                        (floor_even(height as f64 * ratio_w / ratio_h), height)
+                    } else {
+                        let width = floor_even(canvas_w);
+                        (width, floor_even(width as f64 * ratio_h / ratio_w))
+                    };
+                Reframe::BlurPad { width, height }
+            }
+        });
+    }
+
+    /// Frame size after reframing, if it changes.
+    pub fn frame(&self) -> Option<(u32, u32)> {
+        self.reframe.map(|r| match r {
+            Reframe::Crop { width, height } | Reframe::BlurPad { width, height } => (width, height),
+        })
+    }
+
+    /// Filters taking one unlabeled video input to the reframed output.
+    /// Sizes are expressions over the frames ffmpeg actually gets, like the
+    /// scaling filters.
+    pub fn graph(&self) -> FilterGraph {
+        let (Some(reframe), Some((rw, rh))) = (self.reframe, self.ratio) else {
+            return FilterGraph::new();
+        };
+        match reframe {
+            Reframe::Crop { .. } => {
+                let offset = match self.mode {
+                    AspectMode::CropOffset => self.offset,
+                    _ => 0.5,
+                };
+                FilterGraph::from(
+                    FilterChain::new().then(
+                        FilterNode::new("crop")
+                            .arg(format!("trunc(min(iw,ih*{rw}/{rh})/2)*2"))
+                            .arg(format!("trunc(min(ih,iw*{rh}/{rw})/2)*2"))
+                            .arg(format!("(iw-ow)*{offset}"))
+                            .arg(format!("(ih-oh)*{offset}")),
+                    ),
+                )
+            }
+            Reframe::BlurPad { .. } => {
+                let (canvas_w, canvas_h) = canvas_exprs(rw, rh);
+                let wider = format!("gt(iw*{rh},ih*{rw})");
+                FilterGraph::new()
+                    .with(
+                        FilterChain::new()
+                            .then(FilterNode::new("split"))
+                            .output("padbg")
+                            .output("padfg"),
+                    )
+                    .with(
+                        FilterChain::new()
+                            .input("padbg")
+                            .then(
+                                FilterNode::new("scale")
+                                    .arg(&canvas_w)
+                                    .arg(&canvas_h)
+                                    .opt("force_original_aspect_ratio", "increase"),
+                            )
+                            // The enlarged copy keeps the picture's shape, so
+                            // the side that met the canvas decides the other.
+                            .then(
+                                FilterNode::new("crop")
+                                    .arg(format!("if({wider},trunc(ih*{rw}/{rh}/2)*2,iw)"))
+                                    .arg(format!("if({wider},ih,trunc(iw*{rh}/{rw}/2)*2)")),
+                            )
+                            .then(FilterNode::new("boxblur").arg(BLUR_RADIUS).arg(BLUR_POWER))
+                            .output("padblur"),
+                    )
+                    .with(
+                        FilterChain::new()
+                            .input("padfg")
+                            .then(
+                                FilterNode::new("scale")
+                                    .arg(&canvas_w)
+                                    .arg(&canvas_h)
+                                    .opt("force_original_aspect_ratio", "decrease")
+                                    .opt("force_divisible_by", 2),
+                            )
+                            .output("padpic"),
+                    )
+                    .with(
+                        FilterChain::new()
+                            .input("padblur")
+                            .input("padpic")
+                            .then(FilterNode::new("overlay").arg("(W-w)/2").arg("(H-h)/2"))
+                            .then(FilterNode::new("setsar").arg(1)),
+                    )
+            }
+        }
+    }
+}
+
+/// Canvas size for `--aspect-mode pad` as expressions over `iw`/`ih`. The
+/// canvas keeps the picture's short side (1920x1080 -> 1080x1920); the side
+/// the picture spans is rounded first and the other derived from it.
+fn canvas_exprs(rw: u32, rh: u32) -> (String, String) {
+    let (short_w, short_h) = if rw < rh {
+        ("min(iw,ih)".to_string(), format!("min(iw,ih)*{rh}/{rw}"))
+    } else {
+        (format!("min(iw,ih)*{rw}/{rh}"), "min(iw,ih)".to_string())
+    };
+    let wider_h = format!("trunc({short_h}/2)*2");
+    let wider_w = format!("trunc({wider_h}*{rw}/{rh}/2)*2");
+    let narrower_w = format!("trunc({short_w}/2)*2");
+    let narrower_h = format!("trunc({narrower_w}*{rh}/{rw}/2)*2");
+    let wider = format!("gt(iw*{rh},ih*{rw})");
+    (
+        format!("if({wider},{wider_w},{narrower_w})"),
+        format!("if({wider},{wider_h},{narrower_h})"),
+    )
+}
+
+/// Parses `--aspect W:H`, e.g. `9:16`.
+pub fn parse_aspect(raw: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("`{raw}` must be W:H, e.g. 9:16 or 1:1");
+    let (w, h) = raw.trim().split_once(':').ok_or_else(invalid)?;
+    let w: u32 = w.trim().parse().map_err(|_| invalid())?;
+    let h: u32 = h.trim().parse().map_err(|_| invalid())?;
+    if w == 0 || h == 0 {
+        return Err(invalid());
+    }
+    Ok((w, h))
+}
+
+/// Parses `--crop-offset`, 0..1.
+pub fn validate_crop_offset(raw: &str) -> Result<f64, String> {
+    match raw.parse::<f64>() {
+        Ok(x) if (0.0..=1.0).contains(&x) => Ok(x),
+        _ => Err(format!(
+            "`{raw}` must be between 0 (left/top) and 1 (right/bottom)"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(mode: AspectMode, offset: f64) -> AspectSettings {
+        AspectSettings {
+            ratio: Some((9, 16)),
+            mode,
+            offset,
+            reframe: None,
+        }
+    }
+
+    #[test]
+    fn test_crop_modes() {
+        let mut a = settings(AspectMode::CropCenter, 0.0);
+        a.resolve(1920, 1080);
+        assert_eq!(a.frame(), Some((606, 1080)));
+        assert_eq!(
+            a.graph().to_string(),
+            r"crop=trunc(min(iw\,ih*9/16)/2)*2:trunc(min(ih\,iw*16/9)/2)*2:(iw-ow)*0.5:(ih-oh)*0.5"
+        );
+
+        let mut a = settings(AspectMode::CropOffset, 0.25);
+        a.resolve(1920, 1080);
+        assert!(a
+            .graph()
+            .to_string()
+            .ends_with(":(iw-ow)*0.25:(ih-oh)*0.25"));
+
+        a.resolve(1080, 1920);
+        assert_eq!(a.reframe, None);
+        assert!(a.graph().is_empty());
+    }
+
+    #[test]
+    fn test_blurred_pad_canvas() {
+        let mut a = settings(AspectMode::Pad, 0.5);
+        for (source, canvas) in [
+            ((1920, 1080), (1080, 1920)),
+            ((1440, 1080), (1080, 1920)),
+            ((1080, 1440), (1080, 1920)),
+        ] {
+            a.resolve(source.0, source.1);
+            assert_eq!(a.frame(), Some(canvas), "{source:?}");
+        }
+        a.ratio = Some((1, 1));
+        a.resolve(1920, 1080);
+        assert_eq!(a.frame(), Some((1080, 1080)));
+    }
+
+    #[test]
+    fn test_blurred_pad_graph_follows_the_input() {
+        let mut a = settings(AspectMode::Pad, 0.5);
+        a.resolve(1920, 1080);
+        let graph = a.graph().to_string();
+        // Expressions, not the predicted size, so a misjudged input still
+        // comes out in the target shape.
+        a.resolve(1280, 720);
+        assert_eq!(a.graph().to_string(), graph);
+        assert!(graph.starts_with("split[padbg][padfg];[padbg]scale=if(gt(iw*16\\,ih*9)"));
+        assert!(graph.contains(
+            r"crop=if(gt(iw*16\,ih*9)\,trunc(ih*9/16/2)*2\,iw):if(gt(iw*16\,ih*9)\,ih\,trunc(iw*16/9/2)*2),boxblur=20:5[padblur]"
+        ));
+        assert!(
+            graph.contains(":force_original_aspect_ratio=decrease:force_divisible_by=2[padpic]")
+        );
+        assert!(graph.ends_with("[padblur][padpic]overlay=(W-w)/2:(H-h)/2,setsar=1"));
+    }
+
+    #[test]
+    fn test_parse_aspect() {
+        assert_eq!(parse_aspect("9:16"), Ok((9, 16)));
+        assert!(parse_aspect("16x9").is_err());
+        assert!(parse_aspect("0:1").is_err());
+        assert!(validate_crop_offset("1.5").is_err());
+    }
+}
@@ -0,0 +1,19 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How long ffmpeg gets to finish its trailer after `q` before it is killed.
+pub const CANCEL_GRACE: Duration = Duration::from_secs(5);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Turns Ctrl-C (and SIGTERM/SIGHUP) into a cancellation request instead of
+/// killing the process.
+/// ffmpeg runs in its own process group, so only we see the signal and can
+/// stop it cleanly.
+pub fn install() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| REQUESTED.store(true, Ordering::SeqCst))
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
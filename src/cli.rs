@@ -1,6 +1,20 @@
-use crate::filters::{validate_percent_range, validate_scale_height};
-use anyhow::{bail, Result};
+use crate::aspect::{parse_aspect, validate_crop_offset, AspectMode, AspectSettings};
+use crate::color::{ColorSettings, ColorSpace, Tonemap};
+use crate::encoder::{parse_bit_depth, EncoderSettings, VideoEncoder};
+use crate::ffmpeg::RateControl;
+use crate::filters::{
+    validate_percent_range, validate_scale_height, Direction, DEFAULT_REVERSE_MEMORY_LIMIT,
+};
+use crate::framerate::{validate_fps, FrameRateSettings};
+use crate::progress::ProgressFormat;
+use crate::quality::QualityTarget;
+use crate::scale::{
+    parse_fit, validate_scale_factor, validate_width, Resize, ScaleSettings, Scaler,
+};
+use crate::segments::{parse_speed_map, SpeedKeyframe, VariableSpeed};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{ArgAction, Parser, ValueHint};
+use serde::Serialize;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 
@@ -23,6 +37,54 @@ pub struct Cli {
     #[arg(short = 's', long, default_value = "1.0")]
     pub speed: f64,
 
+    /// Speed keyframes as <seconds>:<speed>, e.g. "0:1.0,30:4.0,45:1.0"
+    #[arg(long, conflicts_with = "speed_map_file")]
+    pub speed_map: Option<String>,
+
+    /// File with speed keyframes (same syntax as --speed-map, one per line allowed)
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub speed_map_file: Option<PathBuf>,
+
+    /// Speed for speech (non-silent) parts; enables silence-aware variable speed
+    #[arg(long, conflicts_with_all = ["speed_map", "speed_map_file"])]
+    pub speech_speed: Option<f64>,
+
+    /// Speed for silent parts detected by silencedetect (default: --speed)
+    #[arg(long, conflicts_with_all = ["speed_map", "speed_map_file"])]
+    pub silence_speed: Option<f64>,
+
+    /// Noise floor in dB below which audio counts as silence
+    #[arg(long, default_value = "-30", allow_hyphen_values = true)]
+    pub silence_threshold: f64,
+
+    /// Minimum length in seconds for a quiet stretch to count as silence
+    #[arg(long, default_value = "0.5")]
+    pub silence_min_duration: f64,
+
+    /// Play the clip backwards (buffers the whole clip in memory)
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "boomerang")]
+    pub reverse: bool,
+
+    /// Play forward, then backwards (buffers the whole clip in memory)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub boomerang: bool,
+
+    /// Refuse --reverse/--boomerang when the estimated buffer exceeds this (e.g. 2GB, 512MiB)
+    #[arg(long, default_value = DEFAULT_REVERSE_MEMORY_LIMIT, value_parser = parse_byte_size)]
+    pub reverse_memory_limit: u64,
+
+    /// Fit the output under this size with a two-pass bitrate encode (e.g. 25MB, 8MiB)
+    #[arg(long, value_parser = parse_byte_size)]
+    pub target_size: Option<u64>,
+
+    /// Pick the highest CRF whose samples reach this SSIM (0..1, e.g. 0.98)
+    #[arg(long, conflicts_with_all = ["target_psnr", "target_size"])]
+    pub target_ssim: Option<f64>,
+
+    /// Pick the highest CRF whose samples reach this average PSNR in dB (e.g. 42)
+    #[arg(long, conflicts_with = "target_size")]
+    pub target_psnr: Option<f64>,
+
     /// x264 CRF (used only if we re-encode video)
     #[arg(long, default_value = "17")]
     pub crf: u8,
@@ -31,6 +93,46 @@ pub struct Cli {
     #[arg(long, default_value = "slow")]
     pub preset: String,
 
+    /// Video encoder used when re-encoding
+    #[arg(long, value_enum, default_value_t = VideoEncoder::X264)]
+    pub encoder: VideoEncoder,
+
+    /// Encoder tune (x264: film, animation, grain, stillimage, ...; x265: animation, grain, ...)
+    #[arg(long)]
+    pub tune: Option<String>,
+
+    /// Encoder profile for device compatibility (e.g. high, main, baseline, main10)
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Encoder level for device compatibility (e.g. 4.1)
+    #[arg(long)]
+    pub level: Option<String>,
+
+    /// Maximum keyframe interval in frames
+    #[arg(long, alias = "keyint", value_name = "FRAMES")]
+    pub gop: Option<u32>,
+
+    /// Maximum consecutive B-frames (0 disables them)
+    #[arg(long)]
+    pub bframes: Option<u32>,
+
+    /// Extra libx264 options as key=value:key=value
+    #[arg(long, conflicts_with = "x265_params", allow_hyphen_values = true)]
+    pub x264_params: Option<String>,
+
+    /// Extra libx265 options as key=value:key=value
+    #[arg(long, allow_hyphen_values = true)]
+    pub x265_params: Option<String>,
+
+    /// Output pixel format (default: follow the source when the encoder supports it)
+    #[arg(long, conflicts_with = "bit_depth")]
+    pub pix_fmt: Option<String>,
+
+    /// Output bit depth, 8 or 10, keeping the source chroma subsampling
+    #[arg(long, value_parser = parse_bit_depth)]
+    pub bit_depth: Option<u8>,
+
     /// Denoise 0..100 (50 = unchanged; <=50 off; >50 more denoise)
     #[arg(long, value_parser = validate_percent_range)]
     pub denoise: Option<u8>,
@@ -39,6 +141,61 @@ pub struct Cli {
     #[arg(long, value_parser = validate_scale_height)]
     pub scale: Option<u32>,
 
+    /// Output width. Height auto-calculated to keep aspect. Must be even.
+    #[arg(long, value_parser = validate_width, conflicts_with = "scale")]
+    pub width: Option<u32>,
+
+    /// Scale both sides by this factor (e.g., 0.5)
+    #[arg(long, value_parser = validate_scale_factor, conflicts_with_all = ["scale", "width"])]
+    pub scale_factor: Option<f64>,
+
+    /// Largest size that fits inside WIDTHxHEIGHT keeping aspect (e.g., 1920x1080)
+    #[arg(long, value_name = "WxH", value_parser = parse_fit,
+          conflicts_with_all = ["scale", "width", "scale_factor"])]
+    pub fit: Option<(u32, u32)>,
+
+    /// With --fit, pad to the full box instead of leaving the output smaller
+    #[arg(long, action = ArgAction::SetTrue, requires = "fit")]
+    pub pad: bool,
+
+    /// Skip scaling when the source is already smaller than the requested size
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub no_upscale: bool,
+
+    /// Scaling algorithm (default: ffmpeg's bicubic)
+    #[arg(long, value_enum)]
+    pub scaler: Option<Scaler>,
+
+    /// Reframe to this aspect ratio (e.g., 9:16, 1:1) after scaling
+    #[arg(long, value_name = "W:H", value_parser = parse_aspect)]
+    pub aspect: Option<(u32, u32)>,
+
+    /// How --aspect reframes: pad (blurred background), crop-center or crop-offset
+    #[arg(long, value_enum, default_value_t = AspectMode::Pad, requires = "aspect")]
+    pub aspect_mode: AspectMode,
+
+    /// Crop window position for --aspect-mode crop-offset: 0 = left/top, 1 = right/bottom
+    #[arg(long, value_parser = validate_crop_offset,
+          required_if_eq("aspect_mode", "crop-offset"))]
+    pub crop_offset: Option<f64>,
+
+    /// Output frame rate (e.g., 30, 29.97 or 30000/1001); makes the output constant frame rate
+    #[arg(long, value_parser = validate_fps)]
+    pub fps: Option<String>,
+
+    /// Convert variable frame rate input to constant, at the nearest standard rate
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub cfr: bool,
+
+    /// Convert to and tag this colour space (default: keep the source's,
+    /// converting BT.601 to BT.709 when SD is scaled to HD)
+    #[arg(long, value_enum)]
+    pub color_space: Option<ColorSpace>,
+
+    /// Tone-map HDR (PQ/HLG) sources to SDR with this curve (default: hable; off keeps HDR)
+    #[arg(long, value_enum)]
+    pub tonemap: Option<Tonemap>,
+
     /// Sharpen 0..100 (50 = unchanged; <50 blur; >50 sharpen)
     #[arg(long, value_parser = validate_percent_range)]
     pub sharpen: Option<u8>,
@@ -55,10 +212,66 @@ pub struct Cli {
     #[arg(long, value_parser = validate_percent_range)]
     pub brightness: Option<u8>,
 
+    /// Raw ffmpeg video filters to run before the generated chain (e.g. "crop=1280:720")
+    #[arg(long, value_name = "FILTERS", allow_hyphen_values = true)]
+    pub vf_pre: Option<String>,
+
+    /// Raw ffmpeg video filters to run after the generated chain
+    #[arg(long, value_name = "FILTERS", allow_hyphen_values = true)]
+    pub vf_post: Option<String>,
+
+    /// Raw ffmpeg audio filters to run before the generated chain (e.g. "highpass=f=80")
+    #[arg(long, value_name = "FILTERS", allow_hyphen_values = true)]
+    pub af_pre: Option<String>,
+
+    /// Raw ffmpeg audio filters to run after the generated chain
+    #[arg(long, value_name = "FILTERS", allow_hyphen_values = true)]
+    pub af_post: Option<String>,
+
+    /// After encoding, write a PSNR/SSIM(/VMAF) report vs the source (.json or .md)
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub report: Option<PathBuf>,
+
+    /// Probe and plan only: print the ffmpeg command(s) and filter breakdown, then exit
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub dry_run: bool,
+
+    /// Print each ffmpeg command before running it
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub print_command: bool,
+
+    /// If stream copy fails (container mismatch, broken timestamps), retry with a re-encode
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub fallback: bool,
+
+    /// After encoding, decode the whole output to catch corrupt frames
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub verify_decode: bool,
+
+    /// Fail instead of replacing an existing output file
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "skip_existing")]
+    pub no_overwrite: bool,
+
+    /// Do nothing (and succeed) when the output file already exists
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub skip_existing: bool,
+
+    /// On Ctrl-C, keep the partially written output instead of deleting it
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub keep_partial: bool,
+
+    /// Progress output: bar (interactive), plain (one line per update) or json (NDJSON events)
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Bar)]
+    pub progress_format: ProgressFormat,
+
     /// Show raw ffmpeg logs (useful for debugging)
     #[arg(long, action = ArgAction::SetTrue)]
     pub verbose: bool,
 
+    /// Kill ffmpeg if its progress stays frozen this many seconds (default: wait forever)
+    #[arg(long, value_name = "SECONDS")]
+    pub stall_timeout: Option<u64>,
+
     /// Threads to allow ffmpeg (0 = auto/max)
     #[arg(long, default_value = "0")]
     pub threads: u16,
@@ -72,20 +285,59 @@ pub struct Cli {
     pub ffprobe: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone)]
+/// What to do when the output path already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingOutput {
+    /// Replace it once the new encode has succeeded.
+    Overwrite,
+    Fail,
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct AppConfig {
     pub input: PathBuf,
     pub output: PathBuf,
     pub speed: f64,
+    pub variable_speed: Option<VariableSpeed>,
+    pub speed_map: Option<Vec<SpeedKeyframe>>,
+    pub direction: Direction,
+    pub reverse_memory_limit: u64,
+    pub target_size: Option<u64>,
+    pub quality_target: Option<QualityTarget>,
+    /// `--crf` (or the search's pick); the report shows `rate_control`.
+    #[serde(skip)]
     pub crf: u8,
+    /// Rate control of the finished encode; `None` until it has run and
+    /// when the video was stream-copied.
+    pub rate_control: Option<RateControl>,
     pub preset: String,
+    pub encoder: EncoderSettings,
     pub denoise: Option<u8>,
-    pub scale: Option<u32>,
+    pub scale: ScaleSettings,
+    pub aspect: AspectSettings,
+    pub frame_rate: FrameRateSettings,
+    pub color: ColorSettings,
     pub sharpen: Option<u8>,
     pub contrast: Option<u8>,
     pub saturation: Option<u8>,
     pub brightness: Option<u8>,
+    pub vf_pre: Option<String>,
+    pub vf_post: Option<String>,
+    pub af_pre: Option<String>,
+    pub af_post: Option<String>,
+    pub report: Option<PathBuf>,
+    pub dry_run: bool,
+    pub print_command: bool,
+    pub fallback: bool,
+    pub verify_decode: bool,
+    pub existing: ExistingOutput,
+    pub keep_partial: bool,
+    pub progress_format: ProgressFormat,
     pub verbose: bool,
+    /// Seconds without progress before ffmpeg is killed.
+    pub stall_timeout: Option<u64>,
     pub threads: u16,
     pub ffmpeg: Option<PathBuf>,
     pub ffprobe: Option<PathBuf>,
@@ -93,30 +345,157 @@ pub struct AppConfig {
 
 impl Cli {
     pub fn into_config(self) -> Result<AppConfig> {
-        if self.speed <= 0.0 {
-            bail!("Speed must be > 0.0");
+        if !(self.speed.is_finite() && self.speed > 0.0) {
+            bail!("Speed must be a finite number > 0.0");
+        }
+        let variable_speed = if self.speech_speed.is_some() || self.silence_speed.is_some() {
+            let speech = self.speech_speed.unwrap_or(self.speed);
+            let silence = self.silence_speed.unwrap_or(self.speed);
+            if ![speech, silence].iter().all(|s| s.is_finite() && *s > 0.0) {
+                bail!("Speech and silence speeds must be finite numbers > 0.0");
+            }
+            if self.silence_min_duration <= 0.0 {
+                bail!("Minimum silence duration must be > 0.0");
+            }
+            Some(VariableSpeed {
+                speech,
+                silence,
+                threshold_db: self.silence_threshold,
+                min_silence: self.silence_min_duration,
+            })
+        } else {
+            None
+        };
+        let speed_map = match (&self.speed_map, &self.speed_map_file) {
+            (Some(raw), _) => Some(parse_speed_map(raw).map_err(|e| anyhow!("--speed-map: {e}"))?),
+            (None, Some(path)) => {
+                let raw = std::fs::read_to_string(path)
+                    .with_context(|| format!("cannot read speed map {}", path.display()))?;
+                Some(parse_speed_map(&raw).map_err(|e| anyhow!("{}: {e}", path.display()))?)
+            }
+            (None, None) => None,
+        };
+        let quality_target = match (self.target_ssim, self.target_psnr) {
+            (Some(v), _) if !(0.0..=1.0).contains(&v) => bail!("--target-ssim must be in 0..1"),
+            (Some(v), _) => Some(QualityTarget::Ssim(v)),
+            (None, Some(v)) if v <= 0.0 => bail!("--target-psnr must be > 0"),
+            (None, Some(v)) => Some(QualityTarget::Psnr(v)),
+            (None, None) => None,
+        };
+        let params = match (self.encoder, self.x264_params, self.x265_params) {
+            (VideoEncoder::X265, Some(_), _) => bail!("--x264-params needs --encoder x264"),
+            (VideoEncoder::X264, _, Some(_)) => bail!("--x265-params needs --encoder x265"),
+            (_, x264, x265) => non_empty(x264.or(x265)),
+        };
+        let encoder = EncoderSettings {
+            encoder: self.encoder,
+            tune: self.tune,
+            profile: self.profile,
+            level: self.level,
+            gop: self.gop,
+            bframes: self.bframes,
+            params,
+            pix_fmt: non_empty(self.pix_fmt),
+            bit_depth: self.bit_depth,
+        };
+        encoder.validate().map_err(|e| anyhow!(e))?;
+        if encoder.encoder == VideoEncoder::X265 && self.target_size.is_some() {
+            bail!("--target-size two-pass encoding is only supported with --encoder x264");
+        }
+        if self.crop_offset.is_some() && self.aspect_mode != AspectMode::CropOffset {
+            bail!("--crop-offset needs --aspect-mode crop-offset");
+        }
+        if self.stall_timeout == Some(0) {
+            bail!("--stall-timeout must be at least 1 second");
         }
         if !self.input.exists() {
             bail!("Input not found: {}", self.input.display());
         }
+        // Segment modes ignore --speed, so the name says what they do instead.
+        let speed_label = match (&variable_speed, &speed_map) {
+            (Some(vs), _) => format!("speech{}_silence{}", vs.speech, vs.silence),
+            (None, Some(_)) => "speedmap".to_string(),
+            (None, None) => format!("speed{}", self.speed),
+        };
         let output = self
             .output
             .clone()
-            .unwrap_or_else(|| default_output(&self.input, self.speed));
+            .unwrap_or_else(|| default_output(&self.input, &speed_label));
 
         Ok(AppConfig {
             input: self.input,
             output,
             speed: self.speed,
+            variable_speed,
+            speed_map,
+            direction: if self.reverse {
+                Direction::Reverse
+            } else if self.boomerang {
+                Direction::Boomerang
+            } else {
+                Direction::Forward
+            },
+            reverse_memory_limit: self.reverse_memory_limit,
+            target_size: self.target_size,
+            quality_target,
             crf: self.crf,
+            rate_control: None,
             preset: self.preset,
+            encoder,
             denoise: self.denoise,
-            scale: self.scale,
+            scale: ScaleSettings {
+                resize: match (self.scale, self.width, self.scale_factor, self.fit) {
+                    (Some(h), ..) => Some(Resize::Height(h)),
+                    (_, Some(w), ..) => Some(Resize::Width(w)),
+                    (_, _, Some(f), _) => Some(Resize::Factor(f)),
+                    (.., Some((width, height))) => Some(Resize::Fit { width, height }),
+                    _ => None,
+                },
+                pad: self.pad,
+                no_upscale: self.no_upscale,
+                scaler: self.scaler,
+                size: None,
+            },
+            aspect: AspectSettings {
+                ratio: self.aspect,
+                mode: self.aspect_mode,
+                offset: self.crop_offset.unwrap_or(0.5),
+                reframe: None,
+            },
+            frame_rate: FrameRateSettings {
+                fps: self.fps,
+                cfr: self.cfr,
+                target: None,
+            },
+            color: ColorSettings {
+                target: self.color_space,
+                tonemap: self.tonemap,
+                ..Default::default()
+            },
             sharpen: self.sharpen,
             contrast: self.contrast,
             saturation: self.saturation,
             brightness: self.brightness,
+            vf_pre: non_empty(self.vf_pre),
+            vf_post: non_empty(self.vf_post),
+            af_pre: non_empty(self.af_pre),
+            af_post: non_empty(self.af_post),
+            report: self.report,
+            dry_run: self.dry_run,
+            print_command: self.print_command,
+            fallback: self.fallback,
+            verify_decode: self.verify_decode,
+            existing: if self.no_overwrite {
+                ExistingOutput::Fail
+            } else if self.skip_existing {
+                ExistingOutput::Skip
+            } else {
+                ExistingOutput::Overwrite
+            },
+            keep_partial: self.keep_partial,
+            progress_format: self.progress_format,
             verbose: self.verbose,
+            stall_timeout: self.stall_timeout,
             threads: self.threads,
             ffmpeg: self.ffmpeg,
             ffprobe: self.ffprobe,
@@ -124,13 +503,47 @@ impl Cli {
     }
 }
 
-pub fn default_output(input: &Path, speed: f64) -> PathBuf {
+fn non_empty(raw: Option<String>) -> Option<String> {
+    raw.filter(|s| !s.trim().is_empty())
+}
+
+/// `<input>_enhanced_<label>.mp4` next to the input; `label` names the speed
+/// change, e.g. `speed1.25`.
+pub fn default_output(input: &Path, label: &str) -> PathBuf {
     let stem = input
         .file_stem()
         .and_then(OsStr::to_str)
         .unwrap_or("output");
     let parent = input.parent().unwrap_or(Path::new("."));
-    parent.join(format!("{stem}_enhanced_speed{speed}.mp4"))
+    parent.join(format!("{stem}_enhanced_{label}.mp4"))
+}
+
+/// Parses sizes like `25MB`, `512MiB` or `1048576`. `KB`/`MB`/`GB` are decimal,
+/// `KiB`/`MiB`/`GiB` binary; a bare number is bytes.
+pub fn parse_byte_size(raw: &str) -> Result<u64, String> {
+    let trimmed = raw.trim();
+    let split = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(trimmed.len());
+    let (num, unit) = trimmed.split_at(split);
+    let num: f64 = num
+        .parse()
+        .map_err(|_| format!("`{raw}` must be a size like 25MB or 512MiB"))?;
+    let mult: f64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "m" | "mb" => 1e6,
+        "g" | "gb" => 1e9,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        other => return Err(format!("unknown size unit `{other}`")),
+    };
+    let bytes = (num * mult).round();
+    if bytes < 1.0 {
+        return Err("size must be greater than zero".into());
+    }
+    Ok(bytes as u64)
 }
 
 pub fn target_duration_ms(original_seconds: f64, speed: f64) -> u64 {
@@ -141,3 +554,55 @@ pub fn target_duration_ms(original_seconds: f64, speed: f64) -> u64 {
     };
     (target_seconds * 1000.0).max(1.0) as u64
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("25MB"), Ok(25_000_000));
+        assert_eq!(parse_byte_size("512MiB"), Ok(512 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1.5 GB"), Ok(1_500_000_000));
+        assert_eq!(parse_byte_size("4096"), Ok(4096));
+        assert!(parse_byte_size("0MB").is_err());
+        assert!(parse_byte_size("ten MB").is_err());
+        assert!(parse_byte_size("5 parsecs").is_err());
+    }
+
+    #[test]
+    fn test_default_output_names_the_speed_mode() {
+        // into_config wants an input that exists.
+        let input = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        for (args, name) in [
+            (vec!["-s", "1.25"], "Cargo_enhanced_speed1.25.mp4"),
+            (
+                vec!["--speech-speed", "1.3", "--silence-speed", "4"],
+                "Cargo_enhanced_speech1.3_silence4.mp4",
+            ),
+            (
+                vec!["--speed-map", "0:1,30:4"],
+                "Cargo_enhanced_speedmap.mp4",
+            ),
+        ] {
+            let cli = Cli::try_parse_from(["video_enhancer", "-i", input].into_iter().chain(args))
+                .unwrap();
+            let output = cli.into_config().unwrap().output;
+            assert_eq!(output.file_name().unwrap(), name);
+        }
+    }
+
+    #[test]
+    fn test_non_finite_speeds_are_rejected() {
+        for args in [
+            ["--speed", "inf"],
+            ["--speech-speed", "inf"],
+            ["--silence-speed", "NaN"],
+        ] {
+            let cli =
+                Cli::try_parse_from(["video_enhancer", "-i", "in.mp4", args[0], args[1]]).unwrap();
+            let err = cli.into_config().unwrap_err().to_string();
+            assert!(err.contains("finite"), "{args:?}: {err}");
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use crate::graph::FilterNode;
+use crate::probe::VideoInfo;
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// Output heights from here up count as HD, which players assume is BT.709.
+pub const HD_MIN_HEIGHT: u32 = 720;
+
+/// Target for `--color-space`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSpace {
+    /// SD: BT.601 (525-line, or 625-line for 576-line output).
+    Bt601,
+    /// HD: BT.709.
+    Bt709,
+}
+
+/// Curve for `--tonemap`, squeezing HDR highlights into SDR range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tonemap {
+    /// Filmic; keeps highlight detail (default for re-encoded HDR sources).
+    Hable,
+    /// Leaves mid-tones alone and rolls off only the brightest parts.
+    Mobius,
+    /// Simple and bright, but flattens highlights.
+    Reinhard,
+    /// Keep HDR: no tone mapping, HDR tags are carried through.
+    Off,
+}
+
+impl Tonemap {
+    fn name(self) -> &'static str {
+        match self {
+            Tonemap::Hable => "hable",
+            Tonemap::Mobius => "mobius",
+            Tonemap::Reinhard => "reinhard",
+            Tonemap::Off => "none",
+        }
+    }
+}
+
+/// Nominal SDR peak in nits that HDR is mapped onto.
+const SDR_PEAK_NITS: u32 = 100;
+
+/// A colour standard the `colorspace` filter converts between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Standard {
+    Bt601_525,
+    Bt601_625,
+    Bt709,
+    Bt2020,
+}
+
+impl Standard {
+    /// Name for the `colorspace` filter's `all`/`iall` options.
+    fn filter_name(self) -> &'static str {
+        match self {
+            Standard::Bt601_525 => "bt601-6-525",
+            Standard::Bt601_625 => "bt601-6-625",
+            Standard::Bt709 => "bt709",
+            Standard::Bt2020 => "bt2020",
+        }
+    }
+
+    /// Primaries, transfer and matrix tags the `colorspace` filter writes.
+    fn tags(self) -> [&'static str; 3] {
+        match self {
+            Standard::Bt601_525 => ["smpte170m", "smpte170m", "smpte170m"],
+            Standard::Bt601_625 => ["bt470bg", "smpte170m", "bt470bg"],
+            Standard::Bt709 => ["bt709", "bt709", "bt709"],
+            Standard::Bt2020 => ["bt2020", "bt2020-10", "bt2020nc"],
+        }
+    }
+
+    fn is_601(self) -> bool {
+        matches!(self, Standard::Bt601_525 | Standard::Bt601_625)
+    }
+
+    /// The standard a player assumes for untagged video of this height.
+    fn guess(height: u32) -> Standard {
+        match height {
+            h if h >= HD_MIN_HEIGHT => Standard::Bt709,
+            576 => Standard::Bt601_625,
+            _ => Standard::Bt601_525,
+        }
+    }
+}
+
+impl ColorSpace {
+    fn standard(self, height: u32) -> Standard {
+        match self {
+            ColorSpace::Bt709 => Standard::Bt709,
+            ColorSpace::Bt601 if height == 576 => Standard::Bt601_625,
+            ColorSpace::Bt601 => Standard::Bt601_525,
+        }
+    }
+}
+
+/// Colour description as ffprobe and ffmpeg name it; `None` when unknown.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ColorTags {
+    pub primaries: Option<String>,
+    pub transfer: Option<String>,
+    pub matrix: Option<String>,
+    /// `tv` (limited) or `pc` (full).
+    pub range: Option<String>,
+}
+
+impl ColorTags {
+    /// Keeps only values ffprobe actually knows.
+    pub fn new(primaries: &str, transfer: &str, matrix: &str, range: &str) -> Self {
+        let known = |v: &str| {
+            (!v.is_empty() && v != "unknown" && v != "reserved" && v != "unspecified")
+                .then(|| v.to_string())
+        };
+        Self {
+            primaries: known(primaries),
+            transfer: known(transfer),
+            matrix: known(matrix),
+            range: known(range),
+        }
+    }
+
+    /// The standard these tags describe, falling back to what players guess
+    /// from `height` when the matrix is untagged.
+    pub fn standard(&self, height: u32) -> Option<Standard> {
+        match self.matrix.as_deref() {
+            None => Some(Standard::guess(height)),
+            Some("bt709") => Some(Standard::Bt709),
+            Some("smpte170m") => Some(Standard::Bt601_525),
+            Some("bt470bg") => Some(Standard::Bt601_625),
+            Some("bt2020nc" | "bt2020c") => Some(Standard::Bt2020),
+            Some(_) => None,
+        }
+    }
+
+    /// PQ (HDR10) and HLG transfers.
+    pub fn is_hdr(&self) -> bool {
+        matches!(self.transfer.as_deref(), Some("smpte2084" | "arib-std-b67"))
+    }
+
+    /// ffmpeg output options that write these tags.
+    pub fn args(&self) -> Vec<String> {
+        [
+            ("-color_primaries", &self.primaries),
+            ("-color_trc", &self.transfer),
+            ("-colorspace", &self.matrix),
+            ("-color_range", &self.range),
+        ]
+        .into_iter()
+        .filter_map(|(flag, value)| value.as_ref().map(|v| [flag.to_string(), v.clone()]))
+        .flatten()
+        .collect()
+    }
+}
+
+/// Colour handling: the requested target and tone mapping, and after probing
+/// what is actually applied and the tags written to the output.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ColorSettings {
+    pub target: Option<ColorSpace>,
+    /// `--tonemap`; `None` tone-maps re-encoded HDR sources with `Hable`.
+    pub tonemap: Option<Tonemap>,
+    /// Curve applied to this source, if it is HDR and mapping isn't off.
+    pub tonemapped: Option<Tonemap>,
+    /// Source and target standard when the pixels are converted.
+    pub convert: Option<(Standard, Standard)>,
+    pub tags: ColorTags,
+}
+
+impl ColorSettings {
+    /// Tone-maps HDR sources to BT.709, converts BT.601 to BT.709 when SD is
+    /// scaled to HD (or to `target` when given) and carries the source tags
+    /// otherwise. Without `--tonemap`, HDR is only mapped when the video is
+    /// `reencoded` anyway or converted, so a stream copy stays a copy.
+    /// Returns a note when `--tonemap` was asked for an SDR source, and an
+    /// error when HDR is to be converted without tone mapping.
+    pub fn resolve(
+        &mut self,
+        source: &VideoInfo,
+        out_height: u32,
+        reencoded: bool,
+    ) -> Result<Option<String>, String> {
+        let hdr = source.color.is_hdr();
+        self.tonemapped = match self.tonemap {
+            _ if !hdr => None,
+            None if reencoded || self.target.is_some() => Some(Tonemap::Hable),
+            None | Some(Tonemap::Off) => None,
+            Some(curve) => Some(curve),
+        };
+        if hdr && self.tonemapped.is_none() && self.target.is_some() {
+            return Err(
+                "--color-space can't convert PQ/HLG video without tone mapping; \
+                 drop --tonemap off or --color-space"
+                    .to_string(),
+            );
+        }
+        let from = match self.tonemapped {
+            Some(_) => Some(Standard::Bt709),
+            None => source.color.standard(source.height),
+        };
+        let to = match self.target {
+            Some(target) => Some(target.standard(out_height)),
+            None if from.is_some_and(Standard::is_601) && out_height >= HD_MIN_HEIGHT => {
+                Some(Standard::Bt709)
+            }
+            None => None,
+        };
+        self.convert = match (from, to) {
+            (Some(from), Some(to)) if from != to => Some((from, to)),
+            _ => None,
+        };
+        let written = match (self.convert, self.tonemapped) {
+            (Some((_, to)), _) => Some(to),
+            (None, Some(_)) => Some(Standard::Bt709),
+            (None, None) => None,
+        };
+        self.tags = match written {
+            Some(standard) => {
+                let [primaries, transfer, matrix] = standard.tags();
+                // zscale writes limited range after tone mapping.
+                let range = match self.tonemapped {
+                    Some(_) => Some("tv".to_string()),
+                    None => source.color.range.clone(),
+                };
+                ColorTags {
+                    range,
+                    ..ColorTags::new(primaries, transfer, matrix, "")
+                }
+            }
+            None => source.color.clone(),
+        };
+        Ok(match self.tonemap {
+            Some(curve) if !hdr && curve != Tonemap::Off => Some(format!(
+                "Input is not HDR; --tonemap {} has no effect",
+                curve.name()
+            )),
+            _ => None,
+        })
+    }
+
+    /// `zscale`+`tonemap` filters that bring an HDR source to BT.709 SDR in
+    /// `pix_fmt`; empty unless tone mapping applies.
+    pub fn tonemap_filters(&self, pix_fmt: &str) -> Vec<FilterNode> {
+        let Some(curve) = self.tonemapped else {
+            return Vec::new();
+        };
+        vec![
+            FilterNode::new("zscale")
+                .opt("t", "linear")
+                .opt("npl", SDR_PEAK_NITS),
+            FilterNode::new("format").arg("gbrpf32le"),
+            FilterNode::new("zscale").opt("p", "bt709"),
+            FilterNode::new("tonemap")
+                .opt("tonemap", curve.name())
+                .opt("desat", 0),
+            FilterNode::new("zscale")
+                .opt("t", "bt709")
+                .opt("m", "bt709")
+                .opt("r", "tv"),
+            FilterNode::new("format").arg(pix_fmt),
+        ]
+    }
+
+    /// `colorspace` filter performing the conversion, if any.
+    pub fn filter(&self) -> Option<FilterNode> {
+        self.convert.map(|(from, to)| {
+            FilterNode::new("colorspace")
+                .opt("all", to.filter_name())
+                .opt("iall", from.filter_name())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(height: u32, matrix: &str) -> VideoInfo {
+        VideoInfo {
+            width: height * 4 / 3,
+            height,
+            color: ColorTags::new("", "", matrix, "tv"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sd_to_hd_converts_to_bt709() {
+        let mut color = ColorSettings::default();
+        color.resolve(&source(480, ""), 1080, true).unwrap();
+        assert_eq!(
+            color.filter().unwrap().to_string(),
+            "colorspace=all=bt709:iall=bt601-6-525"
+        );
+        assert_eq!(
+            color.tags.args(),
+            [
+                "-color_primaries",
+                "bt709",
+                "-color_trc",
+                "bt709",
+                "-colorspace",
+                "bt709",
+                "-color_range",
+                "tv"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_same_class_carries_tags() {
+        let mut color = ColorSettings::default();
+        color.resolve(&source(576, "bt470bg"), 480, true).unwrap();
+        assert_eq!(color.filter(), None);
+        assert_eq!(color.tags.matrix.as_deref(), Some("bt470bg"));
+
+        color.target = Some(ColorSpace::Bt709);
+        color.resolve(&source(576, "bt470bg"), 576, true).unwrap();
+        assert_eq!(color.convert, Some((Standard::Bt601_625, Standard::Bt709)));
+    }
+
+    #[test]
+    fn test_hdr_is_tonemapped_by_default() {
+        let hlg = VideoInfo {
+            height: 2160,
+            color: ColorTags::new("bt2020", "arib-std-b67", "bt2020nc", "tv"),
+            ..Default::default()
+        };
+        let mut color = ColorSettings::default();
+        color.resolve(&hlg, 1080, true).unwrap();
+        assert_eq!(color.tonemapped, Some(Tonemap::Hable));
+        assert_eq!(color.filter(), None);
+        assert_eq!(color.tags.transfer.as_deref(), Some("bt709"));
+        let filters: Vec<String> = color
+            .tonemap_filters("yuv420p")
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            filters.join(","),
+            "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,\
+             tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p"
+        );
+
+        color.tonemap = Some(Tonemap::Off);
+        assert_eq!(color.resolve(&hlg, 1080, true), Ok(None));
+        assert!(color.tonemap_filters("yuv420p10le").is_empty());
+        assert_eq!(color.tags, hlg.color);
+
+        color.target = Some(ColorSpace::Bt709);
+        assert!(color.resolve(&hlg, 1080, true).is_err());
+    }
+
+    #[test]
+    fn test_hdr_copy_is_not_tonemapped() {
+        let pq = VideoInfo {
+            height: 2160,
+            color: ColorTags::new("bt2020", "smpte2084", "bt2020nc", "tv"),
+            ..Default::default()
+        };
+        // Nothing else touches the video, so it is stream-copied as HDR.
+        let mut color = ColorSettings::default();
+        assert_eq!(color.resolve(&pq, 2160, false), Ok(None));
+        assert_eq!(color.tonemapped, None);
+        assert!(color.tonemap_filters("yuv420p").is_empty());
+        assert_eq!(color.filter(), None);
+        assert_eq!(color.tags, pq.color);
+
+        color.tonemap = Some(Tonemap::Reinhard);
+        color.resolve(&pq, 2160, false).unwrap();
+        assert_eq!(color.tonemapped, Some(Tonemap::Reinhard));
+    }
+
+    #[test]
+    fn test_tonemap_on_sdr_is_noted() {
+        let mut color = ColorSettings {
+            tonemap: Some(Tonemap::Mobius),
+            ..Default::default()
+        };
+        let note = color
+            .resolve(&source(1080, "bt709"), 1080, true)
+            .unwrap()
+            .unwrap();
+        assert!(note.contains("--tonemap mobius"), "{note}");
+        assert_eq!(color.tonemapped, None);
+    }
+
+    #[test]
+    fn test_unknown_tags_are_dropped() {
+        let tags = ColorTags::new("unknown", "", "bt709", "unknown");
+        assert_eq!(tags.args(), ["-colorspace", "bt709"]);
+    }
+}
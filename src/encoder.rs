@@ -0,0 +1,529 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// Video encoder used whenever the video is re-encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoEncoder {
+    /// H.264 via libx264.
+    #[default]
+    X264,
+    /// HEVC via libx265.
+    X265,
+}
+
+const X264_TUNES: &[&str] = &[
+    "film",
+    "animation",
+    "grain",
+    "stillimage",
+    "fastdecode",
+    "zerolatency",
+    "psnr",
+    "ssim",
+];
+const X265_TUNES: &[&str] = &[
+    "animation",
+    "grain",
+    "fastdecode",
+    "zerolatency",
+    "psnr",
+    "ssim",
+];
+const X264_PROFILES: &[&str] = &["baseline", "main", "high", "high10", "high422", "high444"];
+const X265_PROFILES: &[&str] = &[
+    "main",
+    "main10",
+    "mainstillpicture",
+    "main12",
+    "main422-10",
+    "main422-12",
+    "main444-8",
+    "main444-10",
+    "main444-12",
+];
+/// H.264 and HEVC share these level names (HEVC has no 1b).
+const LEVELS: &[&str] = &[
+    "1", "1b", "1.1", "1.2", "1.3", "2", "2.1", "2.2", "3", "3.1", "3.2", "4", "4.1", "4.2", "5",
+    "5.1", "5.2", "6", "6.1", "6.2",
+];
+/// libx264 refuses more consecutive B-frames than this.
+const MAX_BFRAMES: u32 = 16;
+
+/// Chroma subsampling, ordered by how much colour detail is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Chroma {
+    Gray,
+    Yuv420,
+    Yuv422,
+    Yuv444,
+}
+
+/// A pixel format an encoder accepts.
+struct PixFmt {
+    name: &'static str,
+    chroma: Chroma,
+    depth: u8,
+}
+
+const fn fmt(name: &'static str, chroma: Chroma, depth: u8) -> PixFmt {
+    PixFmt {
+        name,
+        chroma,
+        depth,
+    }
+}
+
+const X264_PIX_FMTS: &[PixFmt] = &[
+    fmt("yuv420p", Chroma::Yuv420, 8),
+    fmt("yuv422p", Chroma::Yuv422, 8),
+    fmt("yuv444p", Chroma::Yuv444, 8),
+    fmt("gray", Chroma::Gray, 8),
+    fmt("yuv420p10le", Chroma::Yuv420, 10),
+    fmt("yuv422p10le", Chroma::Yuv422, 10),
+    fmt("yuv444p10le", Chroma::Yuv444, 10),
+    fmt("gray10le", Chroma::Gray, 10),
+];
+const X265_PIX_FMTS: &[PixFmt] = &[
+    fmt("yuv420p", Chroma::Yuv420, 8),
+    fmt("yuv422p", Chroma::Yuv422, 8),
+    fmt("yuv444p", Chroma::Yuv444, 8),
+    fmt("gray", Chroma::Gray, 8),
+    fmt("yuv420p10le", Chroma::Yuv420, 10),
+    fmt("yuv422p10le", Chroma::Yuv422, 10),
+    fmt("yuv444p10le", Chroma::Yuv444, 10),
+    fmt("gray10le", Chroma::Gray, 10),
+    fmt("yuv420p12le", Chroma::Yuv420, 12),
+    fmt("yuv422p12le", Chroma::Yuv422, 12),
+    fmt("yuv444p12le", Chroma::Yuv444, 12),
+    fmt("gray12le", Chroma::Gray, 12),
+];
+/// Used when the source format is unknown or not YUV (e.g. RGB screen captures).
+const FALLBACK_PIX_FMT: &str = "yuv420p";
+
+/// Highest chroma and bit depth a profile allows.
+fn profile_limits(profile: &str) -> (Chroma, u8) {
+    match profile {
+        "baseline" | "main" | "high" | "mainstillpicture" => (Chroma::Yuv420, 8),
+        "high10" | "main10" => (Chroma::Yuv420, 10),
+        "main12" => (Chroma::Yuv420, 12),
+        "high422" | "main422-10" => (Chroma::Yuv422, 10),
+        "main422-12" => (Chroma::Yuv422, 12),
+        "main444-8" => (Chroma::Yuv444, 8),
+        "high444" | "main444-10" => (Chroma::Yuv444, 10),
+        "main444-12" => (Chroma::Yuv444, 12),
+        _ => (Chroma::Yuv444, u8::MAX),
+    }
+}
+
+/// Chroma and bit depth of an ffmpeg pixel format name such as `yuv422p10le`;
+/// `None` for packed, alpha and RGB formats.
+fn classify_pix_fmt(name: &str) -> Option<(Chroma, u8)> {
+    let name = name.replace("yuvj", "yuv");
+    let base = name
+        .strip_suffix("le")
+        .or_else(|| name.strip_suffix("be"))
+        .unwrap_or(&name);
+    match base {
+        "nv12" | "nv21" => return Some((Chroma::Yuv420, 8)),
+        "p010" => return Some((Chroma::Yuv420, 10)),
+        _ => {}
+    }
+    let (chroma, depth) = if let Some(depth) = base.strip_prefix("gray") {
+        (Chroma::Gray, depth)
+    } else {
+        let rest = base.strip_prefix("yuv")?;
+        let chroma = match rest.get(..4)? {
+            "420p" => Chroma::Yuv420,
+            "422p" => Chroma::Yuv422,
+            "444p" => Chroma::Yuv444,
+            _ => return None,
+        };
+        (chroma, &rest[4..])
+    };
+    let depth = if depth.is_empty() {
+        8
+    } else {
+        depth.parse().ok()?
+    };
+    Some((chroma, depth))
+}
+
+/// Parses `--bit-depth`.
+pub fn parse_bit_depth(raw: &str) -> Result<u8, String> {
+    match raw.trim() {
+        "8" => Ok(8),
+        "10" => Ok(10),
+        _ => Err(format!("`{raw}` must be 8 or 10")),
+    }
+}
+
+impl VideoEncoder {
+    pub fn codec_name(self) -> &'static str {
+        match self {
+            VideoEncoder::X264 => "libx264",
+            VideoEncoder::X265 => "libx265",
+        }
+    }
+
+    pub fn tunes(self) -> &'static [&'static str] {
+        match self {
+            VideoEncoder::X264 => X264_TUNES,
+            VideoEncoder::X265 => X265_TUNES,
+        }
+    }
+
+    pub fn profiles(self) -> &'static [&'static str] {
+        match self {
+            VideoEncoder::X264 => X264_PROFILES,
+            VideoEncoder::X265 => X265_PROFILES,
+        }
+    }
+
+    fn pix_fmts(self) -> &'static [PixFmt] {
+        match self {
+            VideoEncoder::X264 => X264_PIX_FMTS,
+            VideoEncoder::X265 => X265_PIX_FMTS,
+        }
+    }
+
+    /// Name of the free-form parameter flag (`x264-params`/`x265-params`).
+    pub fn params_flag(self) -> &'static str {
+        match self {
+            VideoEncoder::X264 => "x264-params",
+            VideoEncoder::X265 => "x265-params",
+        }
+    }
+}
+
+/// Encoder knobs beyond CRF and preset.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EncoderSettings {
+    pub encoder: VideoEncoder,
+    pub tune: Option<String>,
+    pub profile: Option<String>,
+    pub level: Option<String>,
+    /// Maximum keyframe interval in frames.
+    pub gop: Option<u32>,
+    pub bframes: Option<u32>,
+    /// `key=value:key=value` passed to `-x264-params`/`-x265-params`.
+    pub params: Option<String>,
+    /// Output pixel format; `resolve_pix_fmt` fills it from the source when unset.
+    pub pix_fmt: Option<String>,
+    pub bit_depth: Option<u8>,
+}
+
+impl EncoderSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        let enc = self.encoder;
+        check_choice("--tune", self.tune.as_deref(), enc.tunes(), enc)?;
+        check_choice("--profile", self.profile.as_deref(), enc.profiles(), enc)?;
+        let levels: Vec<&str> = LEVELS
+            .iter()
+            .copied()
+            .filter(|l| enc == VideoEncoder::X264 || *l != "1b")
+            .collect();
+        check_choice("--level", self.level.as_deref(), &levels, enc)?;
+        if self.gop == Some(0) {
+            return Err("--gop must be at least 1".into());
+        }
+        if let Some(bf) = self.bframes
+            && bf > MAX_BFRAMES
+        {
+            return Err(format!("--bframes must be 0..{MAX_BFRAMES}"));
+        }
+        if let Some(params) = &self.params {
+            parse_params(params).map_err(|e| format!("--{}: {e}", enc.params_flag()))?;
+        }
+        if self.pix_fmt.is_some() || self.bit_depth.is_some() {
+            self.resolve_pix_fmt(None, None)?;
+        }
+        Ok(())
+    }
+
+    /// The 8-bit format with `source`'s chroma layout, for chains with filters
+    /// (`eq`) that only work on 8-bit frames when no depth was asked for;
+    /// `None` when `source` is 8-bit already or the request decides.
+    pub fn eight_bit_source(&self, source: &str) -> Option<&'static str> {
+        if self.pix_fmt.is_some() || self.bit_depth.is_some() {
+            return None;
+        }
+        match classify_pix_fmt(source)? {
+            (_, depth) if depth <= 8 => None,
+            (Chroma::Gray, _) => Some("gray"),
+            (Chroma::Yuv420, _) => Some("yuv420p"),
+            (Chroma::Yuv422, _) => Some("yuv422p"),
+            (Chroma::Yuv444, _) => Some("yuv444p"),
+        }
+    }
+
+    /// Output pixel format: `--pix-fmt` as given, otherwise the source's chroma
+    /// at `--bit-depth` or the source depth. A source the encoder (or
+    /// `--profile`) can't reproduce is reduced to the closest format below it;
+    /// an explicit request that can't be met is an error. `supported` is what
+    /// the ffmpeg build reports for the encoder; without it the formats the
+    /// encoder usually has are assumed.
+    pub fn resolve_pix_fmt(
+        &self,
+        source: Option<&str>,
+        supported: Option<&[String]>,
+    ) -> Result<&'static str, String> {
+        let enc = self.encoder;
+        let formats: Vec<&PixFmt> = enc
+            .pix_fmts()
+            .iter()
+            .filter(|f| supported.is_none_or(|names| names.iter().any(|n| n == f.name)))
+            .collect();
+        let (max_chroma, max_depth) = self
+            .profile
+            .as_deref()
+            .map_or((Chroma::Yuv444, u8::MAX), profile_limits);
+        let within_profile = |f: &&PixFmt| f.chroma <= max_chroma && f.depth <= max_depth;
+        let profile_note = || {
+            self.profile
+                .as_deref()
+                .map_or(String::new(), |p| format!(" with --profile {p}"))
+        };
+
+        if let Some(name) = &self.pix_fmt {
+            let Some(found) = formats.iter().find(|f| f.name == name) else {
+                let names: Vec<&str> = formats.iter().map(|f| f.name).collect();
+                return Err(format!(
+                    "--pix-fmt `{name}` is not supported by {}; expected one of: {}",
+                    enc.codec_name(),
+                    names.join(", ")
+                ));
+            };
+            if !within_profile(found) {
+                return Err(format!(
+                    "--pix-fmt `{name}` is not allowed{}",
+                    profile_note()
+                ));
+            }
+            return Ok(found.name);
+        }
+
+        let (chroma, source_depth) = source
+            .and_then(classify_pix_fmt)
+            .unwrap_or((Chroma::Yuv420, 8));
+        if let Some(depth) = self.bit_depth {
+            let supported = formats.iter().any(|f| f.depth == depth);
+            if !supported || depth > max_depth {
+                let why = if supported {
+                    profile_note()
+                } else {
+                    format!(" by {}", enc.codec_name())
+                };
+                return Err(format!("--bit-depth {depth} is not supported{why}"));
+            }
+        }
+        let depth = self.bit_depth.unwrap_or(source_depth);
+        Ok(formats
+            .into_iter()
+            .filter(within_profile)
+            .filter(|f| f.chroma <= chroma && f.depth <= depth)
+            .filter(|f| self.bit_depth.is_none_or(|d| f.depth == d))
+            .max_by_key(|f| (f.depth, f.chroma))
+            .map_or(FALLBACK_PIX_FMT, |f| f.name))
+    }
+
+    /// ffmpeg output options after `-c:v` (tune, profile, level, GOP, B-frames
+    /// and the free-form parameters).
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(tune) = &self.tune {
+            args.extend(["-tune".to_string(), tune.clone()]);
+        }
+        if let Some(profile) = &self.profile {
+            args.extend(["-profile:v".to_string(), profile.clone()]);
+        }
+        if let Some(gop) = self.gop {
+            args.extend(["-g".to_string(), gop.to_string()]);
+        }
+        if let Some(bf) = self.bframes {
+            args.extend(["-bf".to_string(), bf.to_string()]);
+        }
+        let mut params: Vec<String> = self.params.iter().cloned().collect();
+        match (self.encoder, &self.level) {
+            (VideoEncoder::X264, Some(level)) => {
+                args.extend(["-level:v".to_string(), level.clone()]);
+            }
+            // libx265 has no -level option; the level goes in its parameters.
+            (VideoEncoder::X265, Some(level)) => params.push(format!("level-idc={level}")),
+            (_, None) => {}
+        }
+        if !params.is_empty() {
+            args.extend([format!("-{}", self.encoder.params_flag()), params.join(":")]);
+        }
+        args
+    }
+}
+
+fn check_choice(
+    flag: &str,
+    value: Option<&str>,
+    allowed: &[&str],
+    encoder: VideoEncoder,
+) -> Result<(), String> {
+    match value {
+        Some(v) if !allowed.contains(&v) => Err(format!(
+            "{flag} `{v}` is not valid for {}; expected one of: {}",
+            encoder.codec_name(),
+            allowed.join(", ")
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Splits `key=value:key=value`, rejecting empty keys and stray separators.
+pub fn parse_params(raw: &str) -> Result<Vec<(&str, &str)>, String> {
+    raw.split(':')
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("`{pair}` is not key=value"))?;
+            let valid_key = !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_key || value.is_empty() {
+                return Err(format!("`{pair}` is not key=value"));
+            }
+            Ok((key, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_per_encoder() {
+        let mut s = EncoderSettings {
+            tune: Some("film".into()),
+            profile: Some("high".into()),
+            level: Some("4.1".into()),
+            ..Default::default()
+        };
+        assert!(s.validate().is_ok());
+
+        s.encoder = VideoEncoder::X265;
+        let err = s.validate().unwrap_err();
+        assert!(
+            err.starts_with("--tune `film` is not valid for libx265"),
+            "{err}"
+        );
+        s.tune = Some("grain".into());
+        assert!(s.validate().unwrap_err().starts_with("--profile `high`"));
+        s.profile = Some("main10".into());
+        assert!(s.validate().is_ok());
+    }
+
+    #[test]
+    fn test_pix_fmt_follows_source() {
+        let s = EncoderSettings::default();
+        assert_eq!(
+            s.resolve_pix_fmt(Some("yuv422p10le"), None),
+            Ok("yuv422p10le")
+        );
+        assert_eq!(s.resolve_pix_fmt(Some("yuvj420p"), None), Ok("yuv420p"));
+        assert_eq!(
+            s.resolve_pix_fmt(Some("yuv420p12le"), None),
+            Ok("yuv420p10le")
+        );
+        assert_eq!(s.resolve_pix_fmt(Some("bgra"), None), Ok("yuv420p"));
+        assert_eq!(s.resolve_pix_fmt(None, None), Ok("yuv420p"));
+
+        let high = EncoderSettings {
+            profile: Some("high".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            high.resolve_pix_fmt(Some("yuv422p10le"), None),
+            Ok("yuv420p")
+        );
+    }
+
+    #[test]
+    fn test_pix_fmt_overrides() {
+        let mut s = EncoderSettings {
+            bit_depth: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(s.resolve_pix_fmt(Some("yuv422p"), None), Ok("yuv422p10le"));
+        s.profile = Some("high".into());
+        assert_eq!(
+            s.validate().unwrap_err(),
+            "--bit-depth 10 is not supported with --profile high"
+        );
+
+        let s = EncoderSettings {
+            pix_fmt: Some("yuv420p12le".into()),
+            ..Default::default()
+        };
+        assert!(s
+            .validate()
+            .unwrap_err()
+            .starts_with("--pix-fmt `yuv420p12le` is not supported by libx264"));
+        assert!(parse_bit_depth("12").is_err());
+    }
+
+    #[test]
+    fn test_pix_fmt_limited_to_build() {
+        // An 8-bit-only libx264 lists no 10-bit formats.
+        let eight_bit: Vec<String> = ["yuv420p", "yuvj420p", "yuv422p", "yuv444p", "nv12"]
+            .map(String::from)
+            .to_vec();
+        let s = EncoderSettings::default();
+        assert_eq!(
+            s.resolve_pix_fmt(Some("yuv420p10le"), Some(&eight_bit)),
+            Ok("yuv420p")
+        );
+        let s = EncoderSettings {
+            bit_depth: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            s.resolve_pix_fmt(None, Some(&eight_bit)),
+            Err("--bit-depth 10 is not supported by libx264".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eq_keeps_deep_sources_at_eight_bits() {
+        let s = EncoderSettings::default();
+        assert_eq!(s.eight_bit_source("yuv422p10le"), Some("yuv422p"));
+        assert_eq!(s.eight_bit_source("yuv420p"), None);
+        let s = EncoderSettings {
+            bit_depth: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(s.eight_bit_source("yuv420p10le"), None);
+    }
+
+    #[test]
+    fn test_parse_params() {
+        assert_eq!(
+            parse_params("ref=4:deblock=-1,-1").unwrap(),
+            vec![("ref", "4"), ("deblock", "-1,-1")]
+        );
+        assert!(parse_params("ref=4:").is_err());
+        assert!(parse_params("nokey").is_err());
+    }
+
+    #[test]
+    fn test_args_put_x265_level_in_params() {
+        let s = EncoderSettings {
+            encoder: VideoEncoder::X265,
+            level: Some("5.1".into()),
+            gop: Some(120),
+            params: Some("aq-mode=3".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            s.args(),
+            vec!["-g", "120", "-x265-params", "aq-mode=3:level-idc=5.1"]
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::fmt;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// How many trailing ffmpeg stderr lines are kept for error messages.
+pub const STDERR_TAIL_LINES: usize = 40;
+/// How many of those are shown to the user.
+pub const STDERR_SHOWN_LINES: usize = 8;
+
+/// Common ffmpeg failures recognised from its stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    UnsupportedCodecForContainer,
+    MissingEncoder,
+    DiskFull,
+    InvalidFilter,
+    PermissionDenied,
+    BrokenTimestamps,
+    Unknown,
+}
+
+impl FailureKind {
+    /// First matching pattern wins, scanning newest lines first.
+    pub fn classify(lines: &[String]) -> Self {
+        const PATTERNS: &[(&str, FailureKind)] = &[
+            ("no space left on device", FailureKind::DiskFull),
+            ("permission denied", FailureKind::PermissionDenied),
+            ("unknown encoder", FailureKind::MissingEncoder),
+            ("encoder not found", FailureKind::MissingEncoder),
+            (
+                "could not find tag for codec",
+                FailureKind::UnsupportedCodecForContainer,
+            ),
+            (
+                "not currently supported in container",
+                FailureKind::UnsupportedCodecForContainer,
+            ),
+            (
+                "codec not supported",
+                FailureKind::UnsupportedCodecForContainer,
+            ),
+            (
+                "non monotonically increasing dts",
+                FailureKind::BrokenTimestamps,
+            ),
+            ("invalid dts", FailureKind::BrokenTimestamps),
+            ("timestamps are unset", FailureKind::BrokenTimestamps),
+            ("no such filter", FailureKind::InvalidFilter),
+            ("error parsing filtergraph", FailureKind::InvalidFilter),
+            (
+                "error parsing a filter description",
+                FailureKind::InvalidFilter,
+            ),
+            ("error initializing filter", FailureKind::InvalidFilter),
+            ("error reinitializing filters", FailureKind::InvalidFilter),
+            ("option not found", FailureKind::InvalidFilter),
+        ];
+        for line in lines.iter().rev() {
+            let lower = line.to_ascii_lowercase();
+            if let Some((_, kind)) = PATTERNS.iter().find(|(p, _)| lower.contains(p)) {
+                return *kind;
+            }
+        }
+        FailureKind::Unknown
+    }
+
+    pub fn summary(self) -> &'static str {
+        match self {
+            FailureKind::UnsupportedCodecForContainer => {
+                "the output container cannot hold this codec"
+            }
+            FailureKind::MissingEncoder => "this ffmpeg build lacks a required encoder",
+            FailureKind::DiskFull => "the disk is full",
+            FailureKind::InvalidFilter => "the filter graph was rejected",
+            FailureKind::PermissionDenied => "permission denied reading input or writing output",
+            FailureKind::BrokenTimestamps => "the input's timestamps cannot be copied as-is",
+            FailureKind::Unknown => "see ffmpeg output below",
+        }
+    }
+
+    pub fn hint(self) -> Option<&'static str> {
+        match self {
+            FailureKind::UnsupportedCodecForContainer => {
+                Some("Use an .mkv output, or pass --fallback to re-encode when copying fails.")
+            }
+            FailureKind::MissingEncoder => Some(
+                "Install an ffmpeg build with the encoder named below, or point --ffmpeg at one.",
+            ),
+            FailureKind::DiskFull => Some("Free some space or write the output elsewhere."),
+            FailureKind::InvalidFilter => {
+                Some("Run with --verbose to see the full graph ffmpeg received.")
+            }
+            FailureKind::PermissionDenied => {
+                Some("Check read access to the input and write access to the output folder.")
+            }
+            FailureKind::BrokenTimestamps => {
+                Some("Pass --fallback to re-encode when copying fails.")
+            }
+            FailureKind::Unknown => None,
+        }
+    }
+
+    /// Failures of a stream copy that a re-encode avoids.
+    pub fn reencode_helps(self) -> bool {
+        matches!(
+            self,
+            FailureKind::UnsupportedCodecForContainer | FailureKind::BrokenTimestamps
+        )
+    }
+}
+
+/// A failed ffmpeg run with the tail of its stderr.
+#[derive(Debug, Error)]
+pub struct FfmpegFailure {
+    /// What was being run, e.g. "ffmpeg" or "sample encode at CRF 20".
+    pub what: String,
+    pub status: String,
+    pub kind: FailureKind,
+    pub tail: Vec<String>,
+}
+
+impl FfmpegFailure {
+    pub fn new(what: impl Into<String>, status: impl fmt::Display, tail: Vec<String>) -> Self {
+        Self {
+            what: what.into(),
+            status: status.to_string(),
+            kind: FailureKind::classify(&tail),
+            tail,
+        }
+    }
+
+    /// The encoder ffmpeg said it lacks, e.g. `libx265` from
+    /// "Unknown encoder 'libx265'".
+    fn missing_encoder(&self) -> Option<&str> {
+        self.tail
+            .iter()
+            .filter(|line| line.to_lowercase().contains("encoder"))
+            .find_map(|line| line.split('\'').nth(1))
+    }
+
+    /// `kind`'s hint, naming the encoder when ffmpeg said which is missing.
+    pub fn hint(&self) -> Option<String> {
+        match (self.kind, self.missing_encoder()) {
+            (FailureKind::MissingEncoder, Some(codec)) => Some(format!(
+                "Install an ffmpeg build with {codec}, or point --ffmpeg at one."
+            )),
+            (kind, _) => kind.hint().map(str::to_string),
+        }
+    }
+}
+
+impl fmt::Display for FfmpegFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed ({}): {}",
+            self.what,
+            self.status,
+            self.kind.summary()
+        )?;
+        if let Some(hint) = self.hint() {
+            write!(f, "\n  hint: {hint}")?;
+        }
+        let shown = &self.tail[self.tail.len().saturating_sub(STDERR_SHOWN_LINES)..];
+        if !shown.is_empty() {
+            write!(f, "\n  last ffmpeg output:")?;
+            for line in shown {
+                write!(f, "\n    {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Failures scripts may want to tell apart, each with its own exit code.
+///
+/// | Code | Variant |
+/// |---|---|
+/// | 1 | anything else (I/O, internal errors) |
+/// | 2 | `InvalidArgs` (clap also exits 2 on usage errors) |
+/// | 3 | `ToolNotFound` |
+/// | 4 | `Probe` |
+/// | 5 | `Encode` |
+/// | 6 | `Verification` |
+/// | 7 | `Stalled` |
+/// | 130 | `Cancelled` (128 + SIGINT, like a shell) |
+#[derive(Debug, Error)]
+pub enum EnhancerError {
+    #[error("invalid arguments: {0}")]
+    InvalidArgs(String),
+    #[error("{tool} not found: {detail}")]
+    ToolNotFound { tool: String, detail: String },
+    #[error("cannot probe {}: {detail}", input.display())]
+    Probe { input: PathBuf, detail: String },
+    #[error(transparent)]
+    Encode(#[from] FfmpegFailure),
+    #[error("cancelled")]
+    Cancelled,
+    #[error("output verification failed: {0}")]
+    Verification(String),
+    #[error("ffmpeg made no progress for {seconds}s and was stopped (--stall-timeout)")]
+    Stalled { seconds: u64 },
+}
+
+impl EnhancerError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            EnhancerError::InvalidArgs(_) => 2,
+            EnhancerError::ToolNotFound { .. } => 3,
+            EnhancerError::Probe { .. } => 4,
+            EnhancerError::Encode(_) => 5,
+            EnhancerError::Verification(_) => 6,
+            EnhancerError::Stalled { .. } => 7,
+            EnhancerError::Cancelled => 130,
+        }
+    }
+}
+
+/// Exit code for any error: the first `EnhancerError` in the context chain
+/// decides, otherwise 1.
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<EnhancerError>())
+        .map_or(1, EnhancerError::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_classify_common_failures() {
+        let cases = [
+            (
+                "[mp4 @ 0x1] Could not find tag for codec vp9 in stream #0, codec not currently supported in container",
+                FailureKind::UnsupportedCodecForContainer,
+            ),
+            ("Unknown encoder 'libx264'", FailureKind::MissingEncoder),
+            (
+                "av_interleaved_write_frame(): No space left on device",
+                FailureKind::DiskFull,
+            ),
+            (
+                "[AVFilterGraph @ 0x1] No such filter: 'hqdn4d'",
+                FailureKind::InvalidFilter,
+            ),
+            ("out.mp4: Permission denied", FailureKind::PermissionDenied),
+            (
+                "[mp4 @ 0x1] Application provided invalid, non monotonically increasing dts to muxer",
+                FailureKind::BrokenTimestamps,
+            ),
+            ("Conversion failed!", FailureKind::Unknown),
+        ];
+        for (text, kind) in cases {
+            assert_eq!(FailureKind::classify(&lines(text)), kind, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_failure_message_shows_hint_and_tail() {
+        let tail = lines("line 1\nUnknown encoder 'libx265'\nConversion failed!");
+        let msg = FfmpegFailure::new("ffmpeg", "exit status: 1", tail).to_string();
+        assert!(msg.starts_with("ffmpeg failed (exit status: 1): this ffmpeg build lacks"));
+        assert!(msg.contains("hint: Install an ffmpeg build with libx265, or point"));
+        assert!(msg.ends_with("    Conversion failed!"));
+    }
+
+    #[test]
+    fn test_exit_code_found_through_context() {
+        use anyhow::Context;
+        let failure = FfmpegFailure::new("ffmpeg", "exit status: 1", Vec::new());
+        let err = Err::<(), _>(EnhancerError::from(failure))
+            .context("pass 2")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), 5);
+        assert_eq!(exit_code(&EnhancerError::Cancelled.into()), 130);
+        assert_eq!(exit_code(&anyhow::anyhow!("boom")), 1);
+    }
+}
@@ -1,53 +1,363 @@
+use crate::cancel::{self, CANCEL_GRACE};
 use crate::cli::AppConfig;
-use anyhow::{anyhow, bail, Context, Result};
+use crate::encoder::VideoEncoder;
+use crate::error::{EnhancerError, FfmpegFailure, STDERR_TAIL_LINES};
+use crate::filters::{FilterPlan, AUDIO_REENCODE};
+use crate::graph::{FilterChain, FilterGraph, FilterNode};
+use crate::probe::{parse_flat, MediaInfo, PROBE_ENTRIES};
+use crate::progress::Heartbeat;
+use crate::quality::QualityTarget;
+use crate::segments::{parse_silencedetect, VariableSpeed};
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Child, ChildStdout, Command, Stdio};
+use std::process::{Child, ChildStdout, Command, ExitStatus, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 use which::which;
 
 #[derive(Debug, Clone)]
 pub struct Tools {
     pub ffmpeg: PathBuf,
     pub ffprobe: PathBuf,
+    /// Pixel formats this build's video encoder accepts, from
+    /// `ffmpeg -h encoder=...`; `None` when it couldn't be asked.
+    pub encoder_pix_fmts: Option<Vec<String>>,
+}
+
+/// Share of the target size reserved for container overhead.
+pub const MUX_OVERHEAD: f64 = 0.02;
+/// Below this the picture falls apart; refuse rather than produce mush.
+pub const MIN_VIDEO_KBPS: u32 = 64;
+/// How often `wait_for_completion` checks for exit, Ctrl-C or a stall.
+const WAIT_POLL: Duration = Duration::from_millis(100);
+
+/// How the video encoder spends bits when we re-encode.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateControl {
+    Crf(u8),
+    /// Average bitrate in kbit/s (two-pass size targeting).
+    Bitrate(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pass {
+    Single,
+    /// Analysis pass: writes encoder stats under the passlog prefix, discards output.
+    First(PathBuf),
+    Second(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encode {
+    pub rate: RateControl,
+    pub pass: Pass,
+    /// Re-encode even streams that need no filtering (copy fallback).
+    pub force_reencode: bool,
+}
+
+impl Encode {
+    /// Whether this encode stream-copies the video of `plan`.
+    pub fn copies_video(&self, plan: &FilterPlan) -> bool {
+        matches!(plan, FilterPlan::Chains { video, .. } if video.is_empty())
+            && matches!(self.rate, RateControl::Crf(_))
+            && !self.force_reencode
+    }
+}
+
+/// Two-pass stats files; removed when dropped.
+#[derive(Debug)]
+pub struct PassLog {
+    pub prefix: PathBuf,
+}
+
+impl PassLog {
+    pub fn new() -> Self {
+        let prefix =
+            std::env::temp_dir().join(format!("video_enhancer-{}-passlog", std::process::id()));
+        Self { prefix }
+    }
+}
+
+impl Drop for PassLog {
+    fn drop(&mut self) {
+        let prefix = self.prefix.to_string_lossy();
+        for suffix in [
+            "-0.log",
+            "-0.log.mbtree",
+            "-0.log.temp",
+            "-0.log.mbtree.temp",
+        ] {
+            let _ = std::fs::remove_file(format!("{prefix}{suffix}"));
+        }
+    }
+}
+
+/// Scratch file in the system temp dir; removed when dropped.
+#[derive(Debug)]
+pub struct TempFile {
+    pub path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("video_enhancer-{}-{name}", std::process::id()));
+        Self { path }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Hidden file next to the real output that encodes write into. It replaces
+/// the output only on `commit`, so a failed run leaves any previous file
+/// intact; otherwise it is removed when dropped.
+#[derive(Debug)]
+pub struct StagedOutput {
+    pub target: PathBuf,
+    pub path: PathBuf,
+    keep: bool,
+}
+
+impl StagedOutput {
+    pub fn new(target: &Path) -> Self {
+        Self {
+            target: target.to_path_buf(),
+            path: staging_path(target, std::process::id()),
+            keep: false,
+        }
+    }
+
+    /// Moves the finished encode over the target.
+    pub fn commit(mut self) -> Result<()> {
+        std::fs::rename(&self.path, &self.target).with_context(|| {
+            format!(
+                "cannot move {} to {}",
+                self.path.display(),
+                self.target.display()
+            )
+        })?;
+        self.keep = true;
+        Ok(())
+    }
+
+    /// Leaves the staged file on disk (e.g. a partial encode the user wants).
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+
+    /// Removes the staged file now rather than on drop.
+    pub fn discard(&mut self) -> std::io::Result<()> {
+        std::fs::remove_file(&self.path)?;
+        self.keep = true;
+        Ok(())
+    }
+}
+
+impl Drop for StagedOutput {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// `dir/out.mp4` -> `dir/.out.partial-<pid>.mp4`; the extension stays last so
+/// ffmpeg still picks the muxer from it.
+fn staging_path(target: &Path, pid: u32) -> PathBuf {
+    let stem = target
+        .file_stem()
+        .map_or("output".into(), |s| s.to_string_lossy());
+    let name = match target.extension() {
+        Some(ext) => format!(".{stem}.partial-{pid}.{}", ext.to_string_lossy()),
+        None => format!(".{stem}.partial-{pid}"),
+    };
+    target.with_file_name(name)
 }
 
 #[derive(Debug)]
 pub struct FfmpegSession {
     pub child: Child,
     pub stdout: ChildStdout,
+    pub stderr: StderrTail,
+}
+
+/// Keeps the last `STDERR_TAIL_LINES` lines of a child's stderr on a
+/// background thread, echoing the raw stream to ours when `echo` is set.
+#[derive(Debug)]
+pub struct StderrTail {
+    handle: thread::JoinHandle<Vec<String>>,
 }
 
-pub fn resolve_tools(ffmpeg: Option<PathBuf>, ffprobe: Option<PathBuf>) -> Result<Tools> {
+impl StderrTail {
+    pub fn spawn<R: Read + Send + 'static>(mut stderr: R, echo: bool) -> Self {
+        let handle = thread::spawn(move || {
+            let mut tail: VecDeque<String> = VecDeque::with_capacity(STDERR_TAIL_LINES);
+            let mut line: Vec<u8> = Vec::new();
+            let mut buf = [0u8; 4096];
+            let mut push = |line: &mut Vec<u8>| {
+                if !line.is_empty() {
+                    if tail.len() == STDERR_TAIL_LINES {
+                        tail.pop_front();
+                    }
+                    tail.push_back(String::from_utf8_lossy(line).trim_end().to_string());
+                    line.clear();
+                }
+            };
+            while let Ok(n) = stderr.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                if echo {
+                    let _ = std::io::stderr().write_all(&buf[..n]);
+                }
+                // ffmpeg redraws its stats line with \r, so treat it as a break too.
+                for &b in &buf[..n] {
+                    match b {
+                        b'\n' | b'\r' => push(&mut line),
+                        _ if line.len() < MAX_LINE_BYTES => line.push(b),
+                        _ => {}
+                    }
+                }
+            }
+            push(&mut line);
+            tail.into_iter().collect()
+        });
+        Self { handle }
+    }
+
+    /// Waits for the stream to close and returns the retained lines.
+    pub fn finish(self) -> Vec<String> {
+        self.handle.join().unwrap_or_default()
+    }
+}
+
+/// Longer stderr lines are truncated in the tail.
+const MAX_LINE_BYTES: usize = 4096;
+
+pub fn resolve_tools(
+    ffmpeg: Option<PathBuf>,
+    ffprobe: Option<PathBuf>,
+    encoder: VideoEncoder,
+) -> Result<Tools> {
+    let ffmpeg = resolve_bin(ffmpeg, "ffmpeg")?;
+    let codec = encoder.codec_name();
+    let help = encoder_help(&ffmpeg, codec);
+    if let Some(help) = &help
+        && unknown_encoder(&String::from_utf8_lossy(&help.stderr))
+    {
+        return Err(EnhancerError::ToolNotFound {
+            tool: format!("{codec} encoder"),
+            detail: format!(
+                "{} was built without it; install an ffmpeg with {codec} or point --ffmpeg at one",
+                ffmpeg.display()
+            ),
+        }
+        .into());
+    }
+    let encoder_pix_fmts =
+        help.and_then(|help| parse_supported_pix_fmts(&String::from_utf8_lossy(&help.stdout)));
     Ok(Tools {
-        ffmpeg: resolve_bin(ffmpeg, "ffmpeg")?,
         ffprobe: resolve_bin(ffprobe, "ffprobe")?,
+        ffmpeg,
+        encoder_pix_fmts,
     })
 }
 
-pub fn probe_duration_seconds(tools: &Tools, input: &Path) -> Result<f64> {
+/// `ffmpeg -h encoder=<codec>`: the encoder's options and the pixel formats
+/// it takes (an 8-bit-only libx264, for one, lists no 10-bit formats).
+/// `None` when ffmpeg couldn't be run.
+fn encoder_help(ffmpeg: &Path, codec: &str) -> Option<Output> {
+    Command::new(ffmpeg)
+        .args(["-hide_banner", "-h", &format!("encoder={codec}")])
+        .output()
+        .ok()
+}
+
+/// Whether `encoder_help`'s stderr says the build doesn't have the encoder.
+fn unknown_encoder(stderr: &str) -> bool {
+    stderr.contains("is not recognized")
+}
+
+fn parse_supported_pix_fmts(help: &str) -> Option<Vec<String>> {
+    help.lines()
+        .find_map(|line| line.trim().strip_prefix("Supported pixel formats:"))
+        .map(|formats| formats.split_whitespace().map(str::to_string).collect())
+}
+
+pub fn probe_media(tools: &Tools, input: &Path) -> Result<MediaInfo> {
     let out = Command::new(&tools.ffprobe)
         .arg("-v")
         .arg("error")
         .arg("-show_entries")
-        .arg("format=duration")
+        .arg(PROBE_ENTRIES)
         .arg("-of")
-        .arg("default=noprint_wrappers=1:nokey=1")
+        .arg("flat")
         .arg(input)
         .output()
         .context("failed to run ffprobe")?;
+    let probe_error = |detail: String| EnhancerError::Probe {
+        input: input.to_path_buf(),
+        detail,
+    };
     if !out.status.success() {
-        bail!("ffprobe error (status {})", out.status);
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let detail = match stderr.lines().last() {
+            Some(line) => format!("ffprobe {} ({line})", out.status),
+            None => format!("ffprobe {}", out.status),
+        };
+        return Err(probe_error(detail).into());
     }
-    let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
-    Ok(s.parse::<f64>().context("cannot parse duration")?)
+    parse_flat(&String::from_utf8_lossy(&out.stdout)).map_err(|e| probe_error(e.to_string()).into())
 }
 
-pub fn spawn_ffmpeg(
+/// Runs an audio-only silencedetect pass and returns `(start, end)` spans in seconds.
+pub fn detect_silence(
+    tools: &Tools,
+    input: &Path,
+    vs: &VariableSpeed,
+    duration: f64,
+) -> Result<Vec<(f64, f64)>> {
+    let out = Command::new(&tools.ffmpeg)
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(input)
+        .arg("-vn")
+        .arg("-af")
+        .arg(format!(
+            "silencedetect=noise={}dB:d={}",
+            vs.threshold_db, vs.min_silence
+        ))
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .stdout(Stdio::null())
+        .output()
+        .context("failed to run ffmpeg silencedetect")?;
+    let out = check_output(out, "silencedetect")?;
+    Ok(parse_silencedetect(
+        &String::from_utf8_lossy(&out.stderr),
+        duration,
+    ))
+}
+
+/// The ffmpeg command for one encode (or analysis pass) writing to `output`.
+pub fn encode_command(
     tools: &Tools,
     cfg: &AppConfig,
-    video_filters: &str,
-    audio_filters: Option<&str>,
+    plan: &FilterPlan,
     audio_codec: &[&str],
-) -> Result<FfmpegSession> {
+    encode: &Encode,
+    output: &Path,
+) -> Command {
     let mut cmd = Command::new(&tools.ffmpeg);
     if !cfg.verbose {
         cmd.arg("-hide_banner")
@@ -55,42 +365,73 @@ pub fn spawn_ffmpeg(
             .arg("-loglevel")
             .arg("error");
     }
+    // Normally our own staging file; the real output is replaced by rename.
     cmd.arg("-y")
         .arg("-progress")
         .arg("-")
         .arg("-i")
         .arg(&cfg.input);
 
-    if !video_filters.is_empty() {
-        cmd.arg("-vf").arg(video_filters);
-        cmd.args(["-c:v", "libx264"]);
-        cmd.args(["-crf", &cfg.crf.to_string()]);
-        cmd.args(["-preset", &cfg.preset]);
-        cmd.args(["-pix_fmt", "yuv420p"]);
-        cmd.args(["-threads", &cfg.threads.to_string()]);
-    } else {
-        cmd.args(["-c:v", "copy"]);
-        if cfg.threads > 0 {
-            cmd.args(["-threads", &cfg.threads.to_string()]);
+    let analysis_pass = matches!(encode.pass, Pass::First(_));
+    match plan {
+        FilterPlan::Chains { video, audio } => {
+            if !encode.copies_video(plan) {
+                if !video.is_empty() {
+                    cmd.arg("-vf").arg(video);
+                }
+                encode_video_args(&mut cmd, cfg, encode);
+            } else {
+                cmd.args(["-c:v", "copy"]);
+                if cfg.threads > 0 {
+                    cmd.args(["-threads", &cfg.threads.to_string()]);
+                }
+            }
+
+            if analysis_pass {
+                cmd.arg("-an");
+            } else if let Some(af) = audio {
+                cmd.arg("-af").arg(af);
+                cmd.args(audio_codec);
+            } else if encode.force_reencode {
+                cmd.args(AUDIO_REENCODE);
+            } else {
+                cmd.args(["-c:a", "copy"]);
+            }
+        }
+        FilterPlan::Graph { graph, audio } => {
+            // The analysis pass only needs video stats, so the audio output
+            // ends in a sink inside the graph instead of being encoded.
+            if *audio && analysis_pass {
+                cmd.arg("-filter_complex")
+                    .arg(format!("{graph};[aout]anullsink"));
+            } else {
+                cmd.arg("-filter_complex").arg(graph);
+            }
+            cmd.args(["-map", "[vout]"]);
+            encode_video_args(&mut cmd, cfg, encode);
+            if *audio && !analysis_pass {
+                cmd.args(["-map", "[aout]"]);
+                cmd.args(AUDIO_REENCODE);
+            }
         }
     }
 
-    if let Some(af) = audio_filters {
-        cmd.arg("-af").arg(af);
-        cmd.args(audio_codec);
+    if analysis_pass {
+        let null_sink = if cfg!(windows) { "NUL" } else { "/dev/null" };
+        cmd.args(["-f", "null", null_sink]);
     } else {
-        cmd.args(["-c:a", "copy"]);
+        cmd.arg(output);
     }
+    cmd
+}
 
-    cmd.arg(&cfg.output);
-
+/// Starts an `encode_command` with progress on stdout and stderr captured.
+pub fn spawn_ffmpeg(mut cmd: Command, verbose: bool) -> Result<FfmpegSession> {
+    detach_from_console_signals(&mut cmd);
     let mut child = cmd
+        .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .stderr(if cfg.verbose {
-            Stdio::inherit()
-        } else {
-            Stdio::null()
-        })
+        .stderr(Stdio::piped())
         .spawn()
         .context("failed to spawn ffmpeg")?;
 
@@ -98,24 +439,482 @@ pub fn spawn_ffmpeg(
         .stdout
         .take()
         .ok_or_else(|| anyhow!("failed to capture ffmpeg stdout"))?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| anyhow!("failed to capture ffmpeg stderr"))?;
+
+    Ok(FfmpegSession {
+        child,
+        stdout,
+        stderr: StderrTail::spawn(stderr, verbose),
+    })
+}
 
-    Ok(FfmpegSession { child, stdout })
+/// `cmd` as a line that can be pasted into a shell (POSIX quoting, or
+/// cmd.exe-style double quotes on Windows).
+pub fn quote_command(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy(), cfg!(windows)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn shell_quote(arg: &str, windows: bool) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.,:/=+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else if windows {
+        format!("\"{}\"", arg.replace('"', "\\\""))
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+fn is_quicktime(output: &Path) -> bool {
+    output
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| ["mp4", "m4v", "mov"].contains(&e.to_ascii_lowercase().as_str()))
+}
+
+fn encode_video_args(cmd: &mut Command, cfg: &AppConfig, encode: &Encode) {
+    let encoder = cfg.encoder.encoder;
+    cmd.args(["-c:v", encoder.codec_name()]);
+    match encode.rate {
+        RateControl::Crf(crf) => cmd.args(["-crf", &crf.to_string()]),
+        RateControl::Bitrate(kbps) => cmd.args(["-b:v", &format!("{kbps}k")]),
+    };
+    match &encode.pass {
+        Pass::Single => {}
+        Pass::First(log) => {
+            cmd.args(["-pass", "1"]).arg("-passlogfile").arg(log);
+        }
+        Pass::Second(log) => {
+            cmd.args(["-pass", "2"]).arg("-passlogfile").arg(log);
+        }
+    }
+    cmd.args(["-preset", &cfg.preset]);
+    cmd.args(cfg.encoder.args());
+    if encoder == VideoEncoder::X265 && is_quicktime(&cfg.output) {
+        // Apple players only accept HEVC in MP4/MOV under the hvc1 tag.
+        cmd.args(["-tag:v", "hvc1"]);
+    }
+    cmd.args([
+        "-pix_fmt",
+        cfg.encoder.pix_fmt.as_deref().unwrap_or("yuv420p"),
+    ]);
+    cmd.args(cfg.color.tags.args());
+    if cfg.frame_rate.target.is_some() {
+        cmd.args(["-fps_mode", "cfr"]);
+    }
+    cmd.args(["-threads", &cfg.threads.to_string()]);
 }
 
-pub fn wait_for_completion(mut child: Child) -> Result<()> {
-    let status = child.wait()?;
+/// Puts the long-running encode in its own process group so Ctrl-C reaches
+/// only us; `wait_for_completion` then asks ffmpeg to stop cleanly.
+fn detach_from_console_signals(cmd: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+}
+
+/// Waits for ffmpeg to exit. On Ctrl-C it is sent `q` (so it writes the
+/// trailer and exits) and killed if still running after `CANCEL_GRACE`.
+/// With `stall_timeout`, it is killed once `heartbeat` has been idle that long.
+pub fn wait_for_completion(
+    mut child: Child,
+    stderr: StderrTail,
+    heartbeat: &Heartbeat,
+    stall_timeout: Option<Duration>,
+) -> Result<()> {
+    let mut quit_sent: Option<Instant> = None;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if let Some(limit) = stall_timeout
+            && quit_sent.is_none()
+            && heartbeat.idle() > limit
+        {
+            let _ = child.kill();
+            let _ = child.wait();
+            stderr.finish();
+            return Err(EnhancerError::Stalled {
+                seconds: limit.as_secs(),
+            }
+            .into());
+        }
+        match quit_sent {
+            None if cancel::requested() => {
+                if let Some(stdin) = child.stdin.as_mut() {
+                    let _ = stdin.write_all(b"q").and_then(|_| stdin.flush());
+                }
+                quit_sent = Some(Instant::now());
+            }
+            Some(at) if at.elapsed() > CANCEL_GRACE => {
+                let _ = child.kill();
+            }
+            _ => {}
+        }
+        thread::sleep(WAIT_POLL);
+    };
+    let tail = stderr.finish();
+    if quit_sent.is_some() {
+        return Err(EnhancerError::Cancelled.into());
+    }
     if !status.success() {
-        bail!("ffmpeg failed with status: {}", status);
+        if cancel::requested() || stopped_by_signal(status) {
+            return Err(EnhancerError::Cancelled.into());
+        }
+        return Err(EnhancerError::from(FfmpegFailure::new("ffmpeg", status, tail)).into());
+    }
+    Ok(())
+}
+
+/// Whether ffmpeg was stopped from outside: killed by a signal, or exiting
+/// with 255 as it does after catching SIGINT/SIGTERM.
+fn stopped_by_signal(status: ExitStatus) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if status.signal().is_some() {
+            return true;
+        }
+    }
+    status.code() == Some(255)
+}
+
+/// Turns a failed short-lived run into an `FfmpegFailure` built from its stderr.
+fn check_output(out: Output, what: impl Into<String>) -> Result<Output> {
+    if out.status.success() {
+        return Ok(out);
+    }
+    // Short runs share our process group, so Ctrl-C already stopped them.
+    if cancel::requested() {
+        return Err(EnhancerError::Cancelled.into());
+    }
+    let text = String::from_utf8_lossy(&out.stderr);
+    let lines: Vec<&str> = text.lines().collect();
+    let tail = lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..]
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+    Err(EnhancerError::from(FfmpegFailure::new(what, out.status, tail)).into())
+}
+
+/// Encodes `window` of the input with the per-frame `video_filters` at `crf`.
+pub fn encode_sample(
+    tools: &Tools,
+    cfg: &AppConfig,
+    window: (f64, f64),
+    video_filters: &FilterGraph,
+    crf: u8,
+    out: &Path,
+) -> Result<()> {
+    let mut cmd = Command::new(&tools.ffmpeg);
+    cmd.args(["-hide_banner", "-nostats", "-loglevel", "error", "-y"])
+        .args(["-ss", &window.0.to_string(), "-t", &window.1.to_string()])
+        .arg("-i")
+        .arg(&cfg.input)
+        .arg("-an");
+    if !video_filters.is_empty() {
+        cmd.arg("-vf").arg(video_filters.to_string());
+    }
+    let encode = Encode {
+        rate: RateControl::Crf(crf),
+        pass: Pass::Single,
+        force_reencode: true,
+    };
+    encode_video_args(&mut cmd, cfg, &encode);
+    let output = cmd
+        .arg(out)
+        .stdout(Stdio::null())
+        .output()
+        .context("failed to run ffmpeg sample encode")?;
+    check_output(output, format!("sample encode at CRF {crf}"))?;
+    Ok(())
+}
+
+/// Scores `sample` against the same window of the source run through
+/// `video_filters`, using the metric of `target`.
+pub fn measure_sample(
+    tools: &Tools,
+    input: &Path,
+    window: (f64, f64),
+    video_filters: &FilterGraph,
+    sample: &Path,
+    target: QualityTarget,
+) -> Result<f64> {
+    let restart = || FilterNode::new("setpts").arg("PTS-STARTPTS");
+    let mut graph = FilterGraph::new()
+        .with(
+            FilterChain::new()
+                .input("0:v")
+                .then(restart())
+                .output("main"),
+        )
+        .with(
+            FilterChain::new()
+                .input("main")
+                .input("ref")
+                .then(FilterNode::new(target.filter_name())),
+        );
+    let reference = video_filters
+        .clone()
+        .then(FilterGraph::from(FilterChain::new().then(restart())))
+        .labeled("1:v", "ref", "null");
+    graph.extend(reference);
+    let out = Command::new(&tools.ffmpeg)
+        .args(["-hide_banner", "-nostats"])
+        .arg("-i")
+        .arg(sample)
+        .args(["-ss", &window.0.to_string(), "-t", &window.1.to_string()])
+        .arg("-i")
+        .arg(input)
+        .arg("-lavfi")
+        .arg(graph.to_string())
+        .args(["-f", "null", "-"])
+        .stdout(Stdio::null())
+        .output()
+        .context("failed to run ffmpeg quality measurement")?;
+    let out = check_output(out, format!("{} measurement", target.filter_name()))?;
+    target
+        .parse_score(&String::from_utf8_lossy(&out.stderr))
+        .ok_or_else(|| anyhow!("no {} score in ffmpeg output", target.filter_name()))
+}
+
+/// Decodes every frame of `path` and returns the errors ffmpeg reported;
+/// empty means the file decoded cleanly.
+pub fn decode_errors(tools: &Tools, path: &Path) -> Result<Vec<String>> {
+    let out = Command::new(&tools.ffmpeg)
+        .args(["-hide_banner", "-nostdin", "-v", "error", "-i"])
+        .arg(path)
+        .args(["-f", "null", "-"])
+        .output()
+        .context("failed to run ffmpeg decode check")?;
+    let mut errors: Vec<String> = String::from_utf8_lossy(&out.stderr)
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(String::from)
+        .collect();
+    if !out.status.success() && errors.is_empty() {
+        errors.push(format!("ffmpeg {}", out.status));
+    }
+    Ok(errors)
+}
+
+/// What a user filter chain is fed, so `check_user_filters` can generate a
+/// test clip of the same shape.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterInput<'a> {
+    Video {
+        width: u32,
+        height: u32,
+        fps: f64,
+        /// Empty leaves the generator's default.
+        pix_fmt: &'a str,
+    },
+    Audio {
+        sample_rate: u32,
+        channels: u32,
+    },
+}
+
+impl FilterInput<'_> {
+    /// lavfi source producing this input, and the option taking the filters.
+    fn lavfi(&self) -> (String, &'static str) {
+        match *self {
+            FilterInput::Video {
+                width,
+                height,
+                fps,
+                pix_fmt,
+            } => {
+                let fps = if fps > 0.0 { fps } else { 25.0 };
+                let mut source = format!("color=c=black:s={width}x{height}:r={fps}");
+                if !pix_fmt.is_empty() {
+                    source.push_str(&format!(",format={pix_fmt}"));
+                }
+                (source, "-vf")
+            }
+            FilterInput::Audio {
+                sample_rate,
+                channels,
+            } => {
+                let layout = match channels {
+                    1 => "mono".to_string(),
+                    2 => "stereo".to_string(),
+                    6 => "5.1".to_string(),
+                    8 => "7.1".to_string(),
+                    n => format!("{n}c"),
+                };
+                (format!("anullsrc=r={sample_rate}:cl={layout}"), "-af")
+            }
+        }
+    }
+}
+
+/// Runs user-supplied `filters` on a tiny generated clip shaped like `input`
+/// so typos and unknown filters surface as argument errors before the real
+/// encode starts, while size-dependent filters such as `crop` still fit.
+pub fn check_user_filters(
+    tools: &Tools,
+    flag: &str,
+    filters: &str,
+    input: &FilterInput,
+) -> Result<()> {
+    let (source, option) = input.lavfi();
+    let out = Command::new(&tools.ffmpeg)
+        .args(["-hide_banner", "-nostdin", "-v", "error"])
+        .args(["-f", "lavfi", "-i", &source, "-t", "0.1"])
+        .args([option, filters])
+        .args(["-f", "null", "-"])
+        .output()
+        .context("failed to run ffmpeg filter check")?;
+    if out.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let detail = stderr
+        .lines()
+        .find(|l| !l.trim().is_empty())
+        .unwrap_or("rejected by ffmpeg");
+    Err(EnhancerError::InvalidArgs(format!("--{flag} `{filters}`: {detail}")).into())
+}
+
+/// Frame size `filters` turn the video `input` into, as ffprobe reports it
+/// for a generated clip; `None` when it can't tell.
+pub fn filtered_size(tools: &Tools, input: &FilterInput, filters: &str) -> Option<(u32, u32)> {
+    let (source, _) = input.lavfi();
+    let out = Command::new(&tools.ffprobe)
+        .args([
+            "-v",
+            "error",
+            "-f",
+            "lavfi",
+            "-i",
+            &format!("{source},{filters}"),
+        ])
+        .args([
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "stream=width,height",
+        ])
+        .args(["-of", "csv=p=0"])
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&out.stdout);
+    let (w, h) = text.trim().split_once(',')?;
+    Some((w.parse().ok()?, h.parse().ok()?))
+}
+
+/// Whether this ffmpeg build ships the named filter (e.g. `libvmaf`).
+pub fn has_filter(tools: &Tools, name: &str) -> bool {
+    Command::new(&tools.ffmpeg)
+        .args(["-hide_banner", "-filters"])
+        .stderr(Stdio::null())
+        .output()
+        .map(|out| {
+            String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .any(|line| line.split_whitespace().nth(1) == Some(name))
+        })
+        .unwrap_or(false)
+}
+
+/// Decodes `source` (input 0, through `reference_graph` which ends in `[vout]`)
+/// and `output` (input 1) side by side and feeds both into each metric filter.
+/// The reference graph reframes the source itself, so sizes already match.
+pub fn compare_quality(
+    tools: &Tools,
+    source: &Path,
+    output: &Path,
+    reference_graph: &str,
+    metric_filters: &[FilterNode],
+) -> Result<()> {
+    let n = metric_filters.len();
+    let mut reference = FilterChain::new()
+        .input("vout")
+        .then(FilterNode::new("setpts").arg("PTS-STARTPTS"))
+        .then(FilterNode::new("split").arg(n));
+    let mut main = FilterChain::new()
+        .input("1:v")
+        .then(FilterNode::new("setpts").arg("PTS-STARTPTS"))
+        .then(FilterNode::new("split").arg(n));
+    for i in 0..n {
+        reference = reference.output(&format!("ref{i}"));
+        main = main.output(&format!("main{i}"));
     }
+    let mut graph = FilterGraph::new().with(reference).with(main);
+    for (i, filter) in metric_filters.iter().enumerate() {
+        graph.push(
+            FilterChain::new()
+                .input(&format!("main{i}"))
+                .input(&format!("ref{i}"))
+                .then(filter.clone()),
+        );
+    }
+
+    let out = Command::new(&tools.ffmpeg)
+        .args(["-hide_banner", "-nostats", "-loglevel", "error"])
+        .arg("-i")
+        .arg(source)
+        .arg("-i")
+        .arg(output)
+        .arg("-filter_complex")
+        .arg(format!("{reference_graph};{graph}"))
+        .args(["-f", "null", "-"])
+        .stdout(Stdio::null())
+        .output()
+        .context("failed to run ffmpeg quality comparison")?;
+    check_output(out, "quality comparison")?;
     Ok(())
 }
 
+/// Video bitrate that lands `duration_ms` of output under `target_bytes` after
+/// the audio track and container overhead.
+pub fn video_kbps_for_size(target_bytes: u64, duration_ms: u64, audio_kbps: u32) -> Result<u32> {
+    let seconds = duration_ms.max(1) as f64 / 1000.0;
+    let total_kbps = target_bytes as f64 * 8.0 * (1.0 - MUX_OVERHEAD) / seconds / 1000.0;
+    let video_kbps = total_kbps - audio_kbps as f64;
+    if video_kbps < MIN_VIDEO_KBPS as f64 {
+        return Err(EnhancerError::InvalidArgs(format!(
+            "Target size is too small: {:.0} kbit/s total for {:.1}s leaves {:.0} kbit/s for \
+             video (minimum {MIN_VIDEO_KBPS})",
+            total_kbps, seconds, video_kbps
+        ))
+        .into());
+    }
+    Ok(video_kbps as u32)
+}
+
 fn resolve_bin(bin_opt: Option<PathBuf>, default: &str) -> Result<PathBuf> {
     if let Some(path) = bin_opt {
         if path.is_file() {
             return Ok(path);
         }
-        bail!("Provided binary not found: {}", path.display());
+        return Err(EnhancerError::ToolNotFound {
+            tool: default.to_string(),
+            detail: format!("provided binary {} does not exist", path.display()),
+        }
+        .into());
     }
 
     which(default)
@@ -127,5 +926,175 @@ fn resolve_bin(bin_opt: Option<PathBuf>, default: &str) -> Result<PathBuf> {
                 Err(which::Error::CannotFindBinaryPath)
             }
         })
-        .with_context(|| format!("`{default}` not found in PATH"))
+        .map_err(|_| {
+            EnhancerError::ToolNotFound {
+                tool: default.to_string(),
+                detail: format!("not in PATH; install it or pass --{default}"),
+            }
+            .into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("-c:v", false), "-c:v");
+        assert_eq!(shell_quote("[vout]", false), "'[vout]'");
+        assert_eq!(shell_quote("it's.mp4", false), r"'it'\''s.mp4'");
+        assert_eq!(shell_quote("", false), "''");
+        assert_eq!(shell_quote("a b\"c", true), r#""a b\"c""#);
+    }
+
+    #[test]
+    fn test_copies_video_only_without_filters_or_fallback() {
+        let plan = FilterPlan::Chains {
+            video: String::new(),
+            audio: None,
+        };
+        let mut encode = Encode {
+            rate: RateControl::Crf(17),
+            pass: Pass::Single,
+            force_reencode: false,
+        };
+        assert!(encode.copies_video(&plan));
+        encode.force_reencode = true;
+        assert!(!encode.copies_video(&plan));
+        encode.force_reencode = false;
+        encode.rate = RateControl::Bitrate(900);
+        assert!(!encode.copies_video(&plan));
+    }
+
+    #[test]
+    fn test_analysis_pass_drops_graph_audio() {
+        use crate::cli::Cli;
+        use clap::Parser;
+        // into_config wants an input that exists.
+        let input = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let cfg = Cli::try_parse_from(["video_enhancer", "-i", input])
+            .unwrap()
+            .into_config()
+            .unwrap();
+        let tools = Tools {
+            ffmpeg: PathBuf::from("ffmpeg"),
+            ffprobe: PathBuf::from("ffprobe"),
+            encoder_pix_fmts: None,
+        };
+        let plan = FilterPlan::Graph {
+            graph: "[0:v]null[vout];[0:a]anull[aout]".to_string(),
+            audio: true,
+        };
+        let args = |pass| {
+            let encode = Encode {
+                rate: RateControl::Bitrate(900),
+                pass,
+                force_reencode: false,
+            };
+            let cmd = encode_command(&tools, &cfg, &plan, &[], &encode, Path::new("out.mp4"));
+            cmd.get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        let first = args(Pass::First(PathBuf::from("passlog")));
+        assert!(first.contains(&"[0:v]null[vout];[0:a]anull[aout];[aout]anullsink".to_string()));
+        assert!(!first.iter().any(|a| a == "[aout]" || a == "-c:a"));
+        let second = args(Pass::Second(PathBuf::from("passlog")));
+        assert!(second.contains(&"[aout]".to_string()));
+    }
+
+    #[test]
+    fn test_staging_path_is_hidden_sibling() {
+        assert_eq!(
+            staging_path(Path::new("clips/out.mp4"), 42),
+            PathBuf::from("clips/.out.partial-42.mp4")
+        );
+        assert_eq!(
+            staging_path(Path::new("out"), 7),
+            PathBuf::from(".out.partial-7")
+        );
+    }
+
+    #[test]
+    fn test_stderr_tail_keeps_last_lines() {
+        let mut text = String::new();
+        for i in 0..100 {
+            text.push_str(&format!("line {i}\n"));
+        }
+        text.push_str("frame=1\rframe=2\rConversion failed!");
+        let tail = StderrTail::spawn(std::io::Cursor::new(text.into_bytes()), false).finish();
+        assert_eq!(tail.len(), STDERR_TAIL_LINES);
+        assert_eq!(
+            tail[tail.len() - 3..],
+            ["frame=1", "frame=2", "Conversion failed!"]
+        );
+    }
+
+    #[test]
+    fn test_video_kbps_for_size() {
+        // 25 MB over 100 s = 2000 kbit/s, minus 2% overhead and 192k audio.
+        let kbps = video_kbps_for_size(25_000_000, 100_000, 192).unwrap();
+        assert_eq!(kbps, 1768);
+
+        let err = video_kbps_for_size(1_000_000, 600_000, 192).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 2);
+    }
+
+    #[test]
+    fn test_unknown_encoder() {
+        assert!(unknown_encoder(
+            "Codec 'libx265' is not recognized by FFmpeg.\n"
+        ));
+        assert!(!unknown_encoder(""));
+    }
+
+    #[test]
+    fn test_parse_supported_pix_fmts() {
+        let help = "Encoder libx264 [libx264 H.264 / AVC / MPEG-4 AVC / MPEG-4 part 10]:
+    General capabilities: dr1 delay threads
+    Threading capabilities: other
+    Supported pixel formats: yuv420p yuvj420p yuv422p yuvj422p yuv444p yuvj444p nv12
+libx264 AVOptions:
+";
+        let formats = parse_supported_pix_fmts(help).unwrap();
+        assert_eq!(formats.len(), 7);
+        assert_eq!(formats[0], "yuv420p");
+        assert_eq!(
+            parse_supported_pix_fmts("Codec 'libx264' is not recognized"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_filter_check_source_matches_input() {
+        let video = FilterInput::Video {
+            width: 1920,
+            height: 1080,
+            fps: 29.97,
+            pix_fmt: "yuv420p10le",
+        };
+        assert_eq!(
+            video.lavfi(),
+            (
+                "color=c=black:s=1920x1080:r=29.97,format=yuv420p10le".to_string(),
+                "-vf"
+            )
+        );
+        let audio = FilterInput::Audio {
+            sample_rate: 44100,
+            channels: 6,
+        };
+        assert_eq!(audio.lavfi().0, "anullsrc=r=44100:cl=5.1");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stopped_by_signal() {
+        use std::os::unix::process::ExitStatusExt;
+        // Raw wait statuses: killed by SIGTERM, exit 255, exit 1.
+        assert!(stopped_by_signal(ExitStatus::from_raw(15)));
+        assert!(stopped_by_signal(ExitStatus::from_raw(255 << 8)));
+        assert!(!stopped_by_signal(ExitStatus::from_raw(1 << 8)));
+    }
 }
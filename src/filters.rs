@@ -1,15 +1,22 @@
+use crate::graph::{FilterChain, FilterGraph, FilterNode};
+use crate::probe::MediaInfo;
+use crate::scale::ScaleSettings;
+use crate::segments::Segment;
+use serde::Serialize;
+
 pub const BRIGHTNESS_MAX: f64 = 0.25;
 pub const CONTRAST_SPAN: f64 = 0.25;
 pub const SAT_SPAN: f64 = 0.25;
 pub const SHARP_MAX: f64 = 1.0;
 pub const DENOISE_LUMA_MAX: f64 = 1.8;
 pub const DENOISE_TEMP_MAX: f64 = 9.0;
+pub const DEFAULT_REVERSE_MEMORY_LIMIT: &str = "4GiB";
 
 pub fn validate_scale_height(raw: &str) -> Result<u32, String> {
     let parsed: u32 = raw
         .parse()
         .map_err(|_| format!("`{raw}` must be a positive even integer"))?;
-    if parsed == 0 || parsed % 2 != 0 {
+    if parsed == 0 || !parsed.is_multiple_of(2) {
         return Err("scale height must be a positive even integer (e.g., 720, 480)".into());
     }
     Ok(parsed)
@@ -30,32 +37,36 @@ pub fn pct_center_norm(pct: u8) -> f64 {
     (pct as f64 - 50.0) / 50.0
 }
 
-pub fn build_video_filters(
+/// The `-vf` chain for the 0..100 enhancement settings, then retiming.
+pub fn video_filter_chain(
     speed: f64,
     denoise: Option<u8>,
-    scale_height: Option<u32>,
+    scale: Option<&ScaleSettings>,
     sharpen: Option<u8>,
     contrast: Option<u8>,
     saturation: Option<u8>,
     brightness: Option<u8>,
-) -> String {
-    let mut parts: Vec<String> = Vec::new();
+) -> FilterChain {
+    let mut chain = FilterChain::new();
 
     if let Some(p) = denoise {
         let norm = (pct_center_norm(p)).max(0.0);
         if norm > 0.0 {
-            let l = DENOISE_LUMA_MAX * norm;
-            let t = DENOISE_TEMP_MAX * norm;
-            parts.push(format!("hqdn3d={l:.3}:{l:.3}:{t:.3}:{t:.3}"));
+            let l = format!("{:.3}", DENOISE_LUMA_MAX * norm);
+            let t = format!("{:.3}", DENOISE_TEMP_MAX * norm);
+            chain.push(FilterNode::new("hqdn3d").arg(&l).arg(&l).arg(&t).arg(&t));
         }
     }
 
     if let Some(p) = sharpen {
         let amt = pct_center_norm(p) * SHARP_MAX;
         if amt.abs() > 1e-6 {
-            parts.push(format!(
-                "unsharp=luma_msize_x=7:luma_msize_y=7:luma_amount={amt:.3}"
-            ));
+            chain.push(
+                FilterNode::new("unsharp")
+                    .opt("luma_msize_x", 7)
+                    .opt("luma_msize_y", 7)
+                    .opt("luma_amount", format!("{amt:.3}")),
+            );
         }
     }
 
@@ -86,51 +97,413 @@ pub fn build_video_filters(
         }
     }
     if need_eq {
-        parts.push(format!(
-            "eq=contrast={:.6}:saturation={:.6}:brightness={:.6}",
-            eq_contrast, eq_saturation, eq_brightness
-        ));
+        chain.push(
+            FilterNode::new("eq")
+                .opt("contrast", format!("{eq_contrast:.6}"))
+                .opt("saturation", format!("{eq_saturation:.6}"))
+                .opt("brightness", format!("{eq_brightness:.6}")),
+        );
     }
 
-    if let Some(h) = scale_height {
-        parts.push(format!("scale=-2:{h}"));
+    if let Some(scale) = scale {
+        chain.extend(scale.filters());
     }
 
     if (speed - 1.0).abs() > 0.000_5 {
-        parts.push(format!("setpts=PTS/{speed}"));
+        chain.push(FilterNode::new("setpts").arg(format!("PTS/{speed}")));
     }
 
-    parts.join(",")
+    chain
+}
+
+/// A chain holding the user's raw --vf/--af-pre or -post filters, if any.
+pub fn user_chain(raw: Option<&str>) -> FilterChain {
+    let mut chain = FilterChain::new();
+    chain.extend(raw.map(FilterNode::raw));
+    chain
+}
+
+/// Wraps a generated graph with the user's --vf/--af-pre and -post filters.
+pub fn with_user_filters(pre: Option<&str>, graph: FilterGraph, post: Option<&str>) -> FilterGraph {
+    FilterGraph::from(user_chain(pre))
+        .then(graph)
+        .then(FilterGraph::from(user_chain(post)))
 }
 
 pub fn build_audio_filters(speed: f64) -> (Option<String>, Vec<&'static str>) {
     if (speed - 1.0).abs() < 0.001 {
         (None, vec!["-c:a", "copy"])
     } else {
-        let mut s = speed;
-        let mut chain: Vec<String> = Vec::new();
-        if s > 2.0 {
-            while s > 2.0 + 1e-6 {
-                chain.push("atempo=2.0".into());
-                s /= 2.0;
+        (
+            Some(audio_filter_chain(speed).to_string()),
+            AUDIO_REENCODE.to_vec(),
+        )
+    }
+}
+
+pub fn audio_filter_chain(speed: f64) -> FilterChain {
+    let mut chain = FilterChain::new();
+    chain.extend(atempo_chain(speed));
+    chain
+}
+
+pub const AUDIO_REENCODE: [&str; 4] = ["-c:a", "aac", "-b:a", "192k"];
+pub const AUDIO_REENCODE_KBPS: u32 = 192;
+
+/// atempo only accepts 0.5..2.0 per instance, so larger factors are chained.
+pub fn atempo_chain(speed: f64) -> Vec<FilterNode> {
+    let mut s = speed;
+    let mut chain: Vec<FilterNode> = Vec::new();
+    if s > 2.0 {
+        while s > 2.0 + 1e-6 {
+            chain.push(FilterNode::new("atempo").arg("2.0"));
+            s /= 2.0;
+        }
+    } else if s < 0.5 {
+        while s < 0.5 - 1e-6 {
+            chain.push(FilterNode::new("atempo").arg("0.5"));
+            s /= 0.5;
+        }
+    }
+    if (s - 1.0).abs() > 1e-3 {
+        chain.push(FilterNode::new("atempo").arg(format!("{s:.6}")));
+    }
+    chain
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Forward,
+    Reverse,
+    /// Forward, then the same clip reversed.
+    Boomerang,
+}
+
+impl Direction {
+    pub fn duration_factor(self) -> u64 {
+        match self {
+            Direction::Boomerang => 2,
+            _ => 1,
+        }
+    }
+
+    /// Appended to the end of the video chain; empty when playing forward.
+    pub fn video_graph(self) -> FilterGraph {
+        self.graph("", "reverse", (1, 0))
+    }
+
+    /// Appended to the end of the audio chain; empty when playing forward.
+    pub fn audio_graph(self) -> FilterGraph {
+        self.graph("a", "areverse", (0, 1))
+    }
+
+    fn graph(self, prefix: &str, reverse: &str, (v, a): (u8, u8)) -> FilterGraph {
+        match self {
+            Direction::Forward => FilterGraph::new(),
+            Direction::Reverse => FilterChain::new().then(FilterNode::new(reverse)).into(),
+            Direction::Boomerang => {
+                let (fwd, bwd, rev) = (
+                    format!("{prefix}fwd"),
+                    format!("{prefix}bwd"),
+                    format!("{prefix}rev"),
+                );
+                FilterGraph::new()
+                    .with(
+                        FilterChain::new()
+                            .then(FilterNode::new(&format!("{prefix}split")))
+                            .output(&fwd)
+                            .output(&bwd),
+                    )
+                    .with(
+                        FilterChain::new()
+                            .input(&bwd)
+                            .then(FilterNode::new(reverse))
+                            .output(&rev),
+                    )
+                    .with(
+                        FilterChain::new().input(&fwd).input(&rev).then(
+                            FilterNode::new("concat")
+                                .opt("n", 2)
+                                .opt("v", v)
+                                .opt("a", a),
+                        ),
+                    )
+            }
+        }
+    }
+}
+
+/// Rough number of bytes `reverse`/`areverse` hold in memory: every decoded
+/// frame (8-bit 4:2:0 after scaling, at `fps` when --fps converts it) plus
+/// the audio as 32-bit float samples.
+pub fn estimate_reverse_bytes(
+    info: &MediaInfo,
+    frame: Option<(u32, u32)>,
+    fps: Option<f64>,
+) -> u64 {
+    let video = info.video.as_ref().map_or(0.0, |v| {
+        let (w, h) = frame.map_or((v.width as f64, v.height as f64), |(w, h)| {
+            (w as f64, h as f64)
+        });
+        let fps = match fps {
+            Some(fps) if fps > 0.0 => fps,
+            _ if v.avg_frame_rate > 0.0 => v.avg_frame_rate,
+            _ => 30.0,
+        };
+        w * h * 1.5 * fps * info.duration
+    });
+    let audio = info.audio.as_ref().map_or(0.0, |a| {
+        a.sample_rate as f64 * a.channels as f64 * 4.0 * info.duration
+    });
+    (video + audio) as u64
+}
+
+/// What ffmpeg should run: plain `-vf`/`-af` chains, or a `-filter_complex`
+/// graph whose outputs are labeled `[vout]` and (if `audio`) `[aout]`.
+#[derive(Debug, Clone)]
+pub enum FilterPlan {
+    Chains {
+        video: String,
+        audio: Option<String>,
+    },
+    Graph {
+        graph: String,
+        audio: bool,
+    },
+}
+
+impl FilterPlan {
+    pub fn touches_audio(&self) -> bool {
+        match self {
+            FilterPlan::Chains { audio, .. } => audio.is_some(),
+            FilterPlan::Graph { audio, .. } => *audio,
+        }
+    }
+
+    /// One filter per line, numbered within each chain, for --dry-run.
+    pub fn describe(&self, copies_video: bool) -> String {
+        let mut out = String::new();
+        match self {
+            FilterPlan::Chains { video, audio } => {
+                if copies_video {
+                    out.push_str("Video: stream copy\n");
+                } else if video.is_empty() {
+                    out.push_str("Video: re-encode, no filters\n");
+                } else {
+                    out.push_str("Video filters (-vf):\n");
+                    push_numbered(&mut out, "  ", video);
+                }
+                match audio {
+                    Some(af) => {
+                        out.push_str("Audio filters (-af):\n");
+                        push_numbered(&mut out, "  ", af);
+                    }
+                    None => out.push_str("Audio: no filters\n"),
+                }
             }
-        } else if s < 0.5 {
-            while s < 0.5 - 1e-6 {
-                chain.push("atempo=0.5".into());
-                s /= 0.5;
+            FilterPlan::Graph { graph, .. } => {
+                out.push_str("Filter graph (-filter_complex):\n");
+                for (i, chain) in split_unescaped(graph, ';').iter().enumerate() {
+                    out.push_str(&format!("  chain {}:\n", i + 1));
+                    push_numbered(&mut out, "    ", chain);
+                }
             }
         }
-        if (s - 1.0).abs() > 1e-3 {
-            chain.push(format!("atempo={s:.6}"));
+        out
+    }
+}
+
+fn push_numbered(out: &mut String, indent: &str, chain: &str) {
+    for (i, filter) in split_unescaped(chain, ',').iter().enumerate() {
+        out.push_str(&format!("{indent}{}. {filter}\n", i + 1));
+    }
+}
+
+/// Splits on `sep` outside single quotes and not preceded by a backslash,
+/// the way ffmpeg's graph parser does.
+pub fn split_unescaped(text: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '\'' {
+            quoted = !quoted;
+        } else if c == sep && !quoted {
+            parts.push(&text[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Trims the input into `segments`, retimes each one on its own and concatenates
+/// them. The `pre` chains run on the input before it is split; `post_video`
+/// (built with speed 1.0) and `post_audio` run on the joined streams.
+pub fn build_segment_graph(
+    segments: &[Segment],
+    pre_video: FilterChain,
+    pre_audio: FilterChain,
+    post_video: FilterGraph,
+    post_audio: FilterGraph,
+    with_audio: bool,
+) -> String {
+    let n = segments.len();
+    let mut graph = FilterGraph::new();
+
+    let mut v_split = FilterChain::new()
+        .input("0:v")
+        .append(pre_video)
+        .then(FilterNode::new("split").arg(n));
+    let mut a_split = FilterChain::new()
+        .input("0:a")
+        .append(pre_audio)
+        .then(FilterNode::new("asplit").arg(n));
+    for i in 0..n {
+        v_split = v_split.output(&format!("vs{i}"));
+        a_split = a_split.output(&format!("as{i}"));
+    }
+    graph.push(v_split);
+    if with_audio {
+        graph.push(a_split);
+    }
+
+    let mut concat = FilterChain::new();
+    for (i, seg) in segments.iter().enumerate() {
+        let (start, end, speed) = (seg.start, seg.end, seg.speed);
+        graph.push(
+            FilterChain::new()
+                .input(&format!("vs{i}"))
+                .then(
+                    FilterNode::new("trim")
+                        .opt("start", format!("{start:.6}"))
+                        .opt("end", format!("{end:.6}")),
+                )
+                .then(FilterNode::new("setpts").arg(format!("(PTS-STARTPTS)/{speed}")))
+                .output(&format!("v{i}")),
+        );
+        concat = concat.input(&format!("v{i}"));
+        if with_audio {
+            let mut chain = FilterChain::new()
+                .input(&format!("as{i}"))
+                .then(
+                    FilterNode::new("atrim")
+                        .opt("start", format!("{start:.6}"))
+                        .opt("end", format!("{end:.6}")),
+                )
+                .then(FilterNode::new("asetpts").arg("PTS-STARTPTS"));
+            chain.extend(atempo_chain(speed));
+            graph.push(chain.output(&format!("a{i}")));
+            concat = concat.input(&format!("a{i}"));
+        }
+    }
+
+    let concat = concat.then(
+        FilterNode::new("concat")
+            .opt("n", n)
+            .opt("v", 1)
+            .opt("a", usize::from(with_audio)),
+    );
+    let concat = if post_video.is_empty() {
+        concat.output("vout")
+    } else {
+        concat.output("vcat")
+    };
+    let concat = match (with_audio, post_audio.is_empty()) {
+        (false, _) => concat,
+        (true, true) => concat.output("aout"),
+        (true, false) => concat.output("acat"),
+    };
+    graph.push(concat);
+    if !post_video.is_empty() {
+        graph.extend(post_video.labeled("vcat", "vout", "null"));
+    }
+    if with_audio && !post_audio.is_empty() {
+        graph.extend(post_audio.labeled("acat", "aout", "anull"));
+    }
+
+    graph.to_string()
+}
+
+/// The source retimed and reframed exactly like the output (speed, segments,
+/// `geometry`, frame rate, direction) but otherwise untouched, ending in
+/// `[vout]`. Reference for quality reports. `pre` runs first; HDR tone
+/// mapping goes there so both sides are compared as SDR.
+pub fn build_reference_graph(
+    speed: f64,
+    segments: Option<&[Segment]>,
+    pre: FilterChain,
+    geometry: FilterGraph,
+    frame_rate: Option<FilterNode>,
+    direction: Direction,
+) -> String {
+    let mut rate = FilterChain::new();
+    rate.extend(frame_rate);
+    let tail = geometry
+        .then(FilterGraph::from(rate))
+        .then(direction.video_graph());
+    match segments {
+        Some(segments) => build_segment_graph(
+            segments,
+            pre,
+            FilterChain::new(),
+            tail,
+            FilterGraph::new(),
+            false,
+        ),
+        None => {
+            let timing = video_filter_chain(speed, None, None, None, None, None, None);
+            FilterGraph::from(pre.append(timing))
+                .then(tail)
+                .labeled("0:v", "vout", "null")
+                .to_string()
         }
-        let af = chain.join(",");
-        (Some(af), vec!["-c:a", "aac", "-b:a", "192k"])
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::graph::escape_filter_value;
+    use crate::scale::Resize;
+
+    /// `--scale 720`.
+    fn scale_to_720() -> ScaleSettings {
+        ScaleSettings {
+            resize: Some(Resize::Height(720)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_split_unescaped_respects_escapes_and_quotes() {
+        let graph = format!(
+            "hqdn3d=2,psnr=stats_file={},drawtext=text='a,b'",
+            escape_filter_value("/tmp/x,y.log")
+        );
+        let parts = split_unescaped(&graph, ',');
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], "hqdn3d=2");
+        assert_eq!(parts[2], "drawtext=text='a,b'");
+    }
+
+    #[test]
+    fn test_describe_chains() {
+        let plan = FilterPlan::Chains {
+            video: "setpts=PTS/2,scale=-2:720".to_string(),
+            audio: Some("atempo=2".to_string()),
+        };
+        assert_eq!(
+            plan.describe(false),
+            "Video filters (-vf):\n  1. setpts=PTS/2\n  2. scale=-2:720\n\
+             Audio filters (-af):\n  1. atempo=2\n"
+        );
+    }
 
     #[test]
     fn test_center_norm() {
@@ -142,26 +515,26 @@ mod tests {
     }
 
     #[test]
-    fn test_build_video_filters_defaults_empty() {
-        let f = build_video_filters(1.0, None, None, None, None, None, None);
+    fn test_video_filter_chain_defaults_empty() {
+        let f = video_filter_chain(1.0, None, None, None, None, None, None).to_string();
         assert!(f.is_empty(), "expected empty filters, got: {}", f);
     }
 
     #[test]
-    fn test_build_video_filters_speed_only() {
-        let f = build_video_filters(1.25, None, None, None, None, None, None);
+    fn test_video_filter_chain_speed_only() {
+        let f = video_filter_chain(1.25, None, None, None, None, None, None).to_string();
         assert_eq!(f, "setpts=PTS/1.25");
     }
 
     #[test]
     fn test_brightness_mapping() {
-        let f = build_video_filters(1.0, None, None, None, None, None, Some(50));
+        let f = video_filter_chain(1.0, None, None, None, None, None, Some(50)).to_string();
         assert!(f.is_empty(), "brightness 50 should be identity, got: {f}");
 
-        let f = build_video_filters(1.0, None, None, None, None, None, Some(100));
+        let f = video_filter_chain(1.0, None, None, None, None, None, Some(100)).to_string();
         assert!(f.contains(&format!("brightness={:.6}", BRIGHTNESS_MAX)));
 
-        let f = build_video_filters(1.0, None, None, None, None, None, Some(0));
+        let f = video_filter_chain(1.0, None, None, None, None, None, Some(0)).to_string();
         assert!(f.contains(&format!("brightness={:.6}", -BRIGHTNESS_MAX)));
     }
 
@@ -169,7 +542,7 @@ mod tests {
     fn test_contrast_saturation_mapping() {
         let c_mult = 1.0 + 0.5 * CONTRAST_SPAN;
         let s_mult = 1.0 + 0.5 * SAT_SPAN;
-        let f = build_video_filters(1.0, None, None, None, Some(75), Some(75), None);
+        let f = video_filter_chain(1.0, None, None, None, Some(75), Some(75), None).to_string();
         assert!(f.contains(&format!("contrast={:.6}", c_mult)));
         assert!(f.contains(&format!("saturation={:.6}", s_mult)));
     }
@@ -177,20 +550,20 @@ mod tests {
     #[test]
     fn test_sharpen_mapping() {
         let amt = 0.5 * SHARP_MAX;
-        let f = build_video_filters(1.0, None, None, Some(75), None, None, None);
+        let f = video_filter_chain(1.0, None, None, Some(75), None, None, None).to_string();
         assert!(f.contains(&format!("luma_amount={:.3}", amt)));
 
         let amt_neg = -0.5 * SHARP_MAX;
-        let f2 = build_video_filters(1.0, None, None, Some(25), None, None, None);
+        let f2 = video_filter_chain(1.0, None, None, Some(25), None, None, None).to_string();
         assert!(f2.contains(&format!("luma_amount={:.3}", amt_neg)));
     }
 
     #[test]
     fn test_denoise_mapping() {
-        let f = build_video_filters(1.0, Some(50), None, None, None, None, None);
+        let f = video_filter_chain(1.0, Some(50), None, None, None, None, None).to_string();
         assert!(f.is_empty() || !f.contains("hqdn3d"));
 
-        let f2 = build_video_filters(1.0, Some(100), None, None, None, None, None);
+        let f2 = video_filter_chain(1.0, Some(100), None, None, None, None, None).to_string();
         assert!(f2.contains(&format!(
             "hqdn3d={:.3}:{:.3}:{:.3}:{:.3}",
             DENOISE_LUMA_MAX, DENOISE_LUMA_MAX, DENOISE_TEMP_MAX, DENOISE_TEMP_MAX
@@ -199,7 +572,8 @@ mod tests {
 
     #[test]
     fn test_scale_filter_added() {
-        let f = build_video_filters(1.0, None, Some(720), None, None, None, None);
+        let scale = scale_to_720();
+        let f = video_filter_chain(1.0, None, Some(&scale), None, None, None, None).to_string();
         assert!(f.contains("scale=-2:720"));
     }
 
@@ -213,4 +587,193 @@ mod tests {
         assert!(af_some.unwrap().contains("atempo=1.25"));
         assert_eq!(a_enc, vec!["-c:a", "aac", "-b:a", "192k"]);
     }
+
+    #[test]
+    fn test_segment_graph() {
+        let segs = vec![
+            Segment {
+                start: 0.0,
+                end: 2.0,
+                speed: 1.0,
+            },
+            Segment {
+                start: 2.0,
+                end: 6.0,
+                speed: 4.0,
+            },
+        ];
+        let g = build_segment_graph(
+            &segs,
+            FilterChain::new(),
+            FilterChain::new(),
+            FilterGraph::new(),
+            FilterGraph::new(),
+            true,
+        );
+        assert!(g.starts_with("[0:v]split=2[vs0][vs1];[0:a]asplit=2[as0][as1];"));
+        assert!(g.contains("[vs1]trim=start=2.000000:end=6.000000,setpts=(PTS-STARTPTS)/4[v1]"));
+        assert!(g.contains("asetpts=PTS-STARTPTS,atempo=2.0,atempo=2.000000[a1]"));
+        assert!(g.ends_with("[v0][a0][v1][a1]concat=n=2:v=1:a=1[vout][aout]"));
+
+        let scale = FilterGraph::from(video_filter_chain(
+            1.0,
+            None,
+            Some(&scale_to_720()),
+            None,
+            None,
+            None,
+            None,
+        ));
+        let g = build_segment_graph(
+            &segs,
+            FilterChain::new(),
+            FilterChain::new(),
+            scale,
+            Direction::Reverse.audio_graph(),
+            false,
+        );
+        assert!(!g.contains("[0:a]"));
+        assert!(g.ends_with("[v0][v1]concat=n=2:v=1:a=0[vcat];[vcat]scale=-2:720[vout]"));
+
+        let g = build_segment_graph(
+            &segs,
+            FilterChain::new(),
+            FilterChain::new(),
+            Direction::Reverse.video_graph(),
+            Direction::Reverse.audio_graph(),
+            true,
+        );
+        assert!(
+            g.ends_with("concat=n=2:v=1:a=1[vcat][acat];[vcat]reverse[vout];[acat]areverse[aout]")
+        );
+
+        let g = build_segment_graph(
+            &segs,
+            FilterChain::new().then(FilterNode::raw("crop=640:360")),
+            FilterChain::new().then(FilterNode::raw("highpass=f=80")),
+            FilterGraph::new(),
+            FilterGraph::new(),
+            true,
+        );
+        assert!(g.starts_with("[0:v]crop=640:360,split=2[vs0][vs1];[0:a]highpass=f=80,asplit=2"));
+    }
+
+    #[test]
+    fn test_user_filters_wrap_generated_chain() {
+        let scale = scale_to_720();
+        let chain = video_filter_chain(2.0, None, Some(&scale), None, None, None, None);
+        let wrapped = with_user_filters(
+            Some("crop=iw/2:ih"),
+            FilterGraph::from(chain),
+            Some("drawgrid=w=100:h=100"),
+        );
+        assert_eq!(
+            wrapped.to_string(),
+            "crop=iw/2:ih,scale=-2:720,setpts=PTS/2,drawgrid=w=100:h=100"
+        );
+        let audio = with_user_filters(
+            None,
+            FilterGraph::from(audio_filter_chain(1.0)),
+            Some("loudnorm"),
+        );
+        assert_eq!(audio.to_string(), "loudnorm");
+    }
+
+    #[test]
+    fn test_direction_chains() {
+        assert!(Direction::Forward.video_graph().is_empty());
+        let scale = FilterGraph::from(video_filter_chain(
+            1.0,
+            None,
+            Some(&scale_to_720()),
+            None,
+            None,
+            None,
+            None,
+        ));
+        assert_eq!(
+            scale.then(Direction::Reverse.video_graph()).to_string(),
+            "scale=-2:720,reverse"
+        );
+        assert_eq!(
+            Direction::Boomerang.video_graph().to_string(),
+            "split[fwd][bwd];[bwd]reverse[rev];[fwd][rev]concat=n=2:v=1:a=0"
+        );
+        let boomerang = Direction::Boomerang.audio_graph().to_string();
+        assert!(boomerang.starts_with("asplit[afwd][abwd];"));
+        assert!(boomerang.ends_with("[afwd][arev]concat=n=2:v=0:a=1"));
+        assert_eq!(Direction::Boomerang.duration_factor(), 2);
+    }
+
+    #[test]
+    fn test_reference_graph() {
+        let none = FilterChain::new;
+        let mut scaled = FilterChain::new();
+        scaled.extend(scale_to_720().filters());
+        assert_eq!(
+            build_reference_graph(
+                1.0,
+                None,
+                none(),
+                FilterGraph::new(),
+                None,
+                Direction::Forward
+            ),
+            "[0:v]null[vout]"
+        );
+        assert_eq!(
+            build_reference_graph(
+                2.0,
+                None,
+                none(),
+                FilterGraph::new(),
+                None,
+                Direction::Reverse
+            ),
+            "[0:v]setpts=PTS/2,reverse[vout]"
+        );
+        assert_eq!(
+            build_reference_graph(
+                1.0,
+                None,
+                FilterChain::new().then(FilterNode::new("zscale").opt("t", "linear")),
+                FilterGraph::from(scaled),
+                Some(FilterNode::new("fps").arg(30)),
+                Direction::Forward
+            ),
+            "[0:v]zscale=t=linear,scale=-2:720,fps=30[vout]"
+        );
+    }
+
+    #[test]
+    fn test_estimate_reverse_bytes() {
+        use crate::probe::{AudioInfo, VideoInfo};
+        let info = MediaInfo {
+            duration: 10.0,
+            video: Some(VideoInfo {
+                width: 1920,
+                height: 1080,
+                avg_frame_rate: 30.0,
+                ..Default::default()
+            }),
+            audio: Some(AudioInfo {
+                sample_rate: 48_000,
+                channels: 2,
+                bit_rate: 0,
+            }),
+        };
+        let video = 1920 * 1080 * 3 / 2 * 300;
+        let audio = 48_000 * 2 * 4 * 10;
+        assert_eq!(estimate_reverse_bytes(&info, None, None), video + audio);
+        // Scaling to 540p quarters the frame buffer.
+        assert_eq!(
+            estimate_reverse_bytes(&info, Some((960, 540)), None),
+            video / 4 + audio
+        );
+        // reverse runs after --fps, so it holds frames at the target rate.
+        assert_eq!(
+            estimate_reverse_bytes(&info, None, Some(15.0)),
+            video / 2 + audio
+        );
+    }
 }
@@ -0,0 +1,137 @@
+use crate::graph::FilterNode;
+use crate::probe::{parse_rational, VideoInfo};
+use serde::Serialize;
+
+/// Relative gap between `r_frame_rate` and `avg_frame_rate` above which the
+/// source is treated as variable frame rate.
+pub const VFR_TOLERANCE: f64 = 0.01;
+
+/// Common rates that `--cfr` snaps a measured average to, as ffmpeg rationals.
+const STANDARD_RATES: &[&str] = &[
+    "24000/1001",
+    "24",
+    "25",
+    "30000/1001",
+    "30",
+    "50",
+    "60000/1001",
+    "60",
+];
+
+/// `--fps`/`--cfr`, and after probing the constant rate the output gets.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FrameRateSettings {
+    /// `--fps` as given, e.g. `30` or `30000/1001`.
+    pub fps: Option<String>,
+    /// Make variable frame rate input constant.
+    pub cfr: bool,
+    /// Output rate when an `fps` filter is added.
+    pub target: Option<String>,
+}
+
+impl FrameRateSettings {
+    /// Picks the output rate. Returns a warning when the source is variable
+    /// frame rate and nothing was asked for.
+    pub fn resolve(&mut self, video: &VideoInfo) -> Option<String> {
+        let vfr = is_vfr(video);
+        self.target = match &self.fps {
+            Some(fps) => Some(fps.clone()),
+            None if self.cfr && vfr => Some(nearest_standard_rate(video.avg_frame_rate)),
+            None => None,
+        };
+        (vfr && self.target.is_none()).then(|| {
+            format!(
+                "Input looks variable frame rate (r_frame_rate {:.3} vs average {:.3} fps); \
+                 pass --cfr or --fps to make it constant",
+                video.r_frame_rate, video.avg_frame_rate
+            )
+        })
+    }
+
+    /// The target rate in frames per second, if any.
+    pub fn rate(&self) -> Option<f64> {
+        self.target.as_deref().and_then(parse_rational)
+    }
+
+    /// `fps` filter producing the target rate, if any.
+    pub fn filter(&self) -> Option<FilterNode> {
+        self.target
+            .as_ref()
+            .map(|rate| FilterNode::new("fps").arg(rate))
+    }
+}
+
+/// Whether ffprobe's nominal and average rates disagree.
+pub fn is_vfr(video: &VideoInfo) -> bool {
+    let (r, avg) = (video.r_frame_rate, video.avg_frame_rate);
+    r > 0.0 && avg > 0.0 && (r - avg).abs() / r > VFR_TOLERANCE
+}
+
+/// The standard rate closest to `fps`; anything far from all of them is
+/// rounded to whole frames per second.
+fn nearest_standard_rate(fps: f64) -> String {
+    STANDARD_RATES
+        .iter()
+        .map(|raw| (*raw, parse_rational(raw).unwrap_or(0.0)))
+        .filter(|(_, rate)| (rate - fps).abs() / rate <= 0.02)
+        .min_by(|a, b| (a.1 - fps).abs().total_cmp(&(b.1 - fps).abs()))
+        .map_or_else(
+            || fps.round().max(1.0).to_string(),
+            |(raw, _)| raw.to_string(),
+        )
+}
+
+/// Parses `--fps`: a positive number or rational such as `30000/1001`.
+pub fn validate_fps(raw: &str) -> Result<String, String> {
+    match parse_rational(raw.trim()) {
+        Some(fps) if fps > 0.0 && fps <= 1000.0 => Ok(raw.trim().to_string()),
+        _ => Err(format!(
+            "`{raw}` must be a frame rate like 30, 29.97 or 30000/1001"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(r: f64, avg: f64) -> VideoInfo {
+        VideoInfo {
+            r_frame_rate: r,
+            avg_frame_rate: avg,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_vfr_warns_unless_converted() {
+        let phone = video(120.0, 29.6);
+        let mut rate = FrameRateSettings::default();
+        assert!(rate
+            .resolve(&phone)
+            .unwrap()
+            .contains("variable frame rate"));
+        assert_eq!(rate.filter(), None);
+
+        rate.cfr = true;
+        assert_eq!(rate.resolve(&phone), None);
+        assert_eq!(rate.filter().unwrap().to_string(), "fps=30000/1001");
+
+        // Already constant: nothing to convert.
+        assert_eq!(rate.resolve(&video(25.0, 25.0)), None);
+        assert_eq!(rate.target, None);
+    }
+
+    #[test]
+    fn test_explicit_fps() {
+        let mut rate = FrameRateSettings {
+            fps: Some("24".into()),
+            ..Default::default()
+        };
+        assert_eq!(rate.resolve(&video(60.0, 60.0)), None);
+        assert_eq!(rate.filter().unwrap().to_string(), "fps=24");
+        assert_eq!(nearest_standard_rate(14.2), "14");
+        assert!(validate_fps("0").is_err());
+        assert_eq!(validate_fps("30000/1001"), Ok("30000/1001".to_string()));
+    }
+}
@@ -0,0 +1,294 @@
+use std::fmt;
+
+/// Escapes a value such as a file path for use as a filter option inside a
+/// filter graph: once for the option parser, once for the graph parser.
+pub fn escape_filter_value(raw: &str) -> String {
+    let mut option_level = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        if matches!(c, '\\' | '\'' | ':') {
+            option_level.push('\\');
+        }
+        option_level.push(c);
+    }
+    let mut graph_level = String::with_capacity(option_level.len());
+    for c in option_level.chars() {
+        if matches!(c, '\\' | '\'' | '[' | ']' | ',' | ';') {
+            graph_level.push('\\');
+        }
+        graph_level.push(c);
+    }
+    graph_level
+}
+
+/// One filter and its options, e.g. `scale=-2:720` or `eq=contrast=1.1`.
+/// Values are escaped when added, so paths and expressions can be passed as-is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterNode {
+    name: String,
+    args: Vec<String>,
+}
+
+impl FilterNode {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Filter text supplied by the user, emitted verbatim (not escaped).
+    pub fn raw(text: &str) -> Self {
+        Self::new(text.trim())
+    }
+
+    /// Positional option.
+    pub fn arg(mut self, value: impl fmt::Display) -> Self {
+        self.args.push(escape_filter_value(&value.to_string()));
+        self
+    }
+
+    /// `key=value` option.
+    pub fn opt(mut self, key: &str, value: impl fmt::Display) -> Self {
+        self.args
+            .push(format!("{key}={}", escape_filter_value(&value.to_string())));
+        self
+    }
+}
+
+impl fmt::Display for FilterNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if !self.args.is_empty() {
+            write!(f, "={}", self.args.join(":"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Filters run one after another, with optional labeled input and output pads:
+/// `[in0][in1]a,b,c[out]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilterChain {
+    inputs: Vec<String>,
+    filters: Vec<FilterNode>,
+    outputs: Vec<String>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn input(mut self, label: &str) -> Self {
+        self.inputs.push(label.to_string());
+        self
+    }
+
+    pub fn then(mut self, node: FilterNode) -> Self {
+        self.filters.push(node);
+        self
+    }
+
+    pub fn output(mut self, label: &str) -> Self {
+        self.outputs.push(label.to_string());
+        self
+    }
+
+    pub fn push(&mut self, node: FilterNode) {
+        self.filters.push(node);
+    }
+
+    /// Appends the filters of `other`; its pad labels are dropped.
+    pub fn append(mut self, other: FilterChain) -> Self {
+        self.filters.extend(other.filters);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Whether a filter called `name` is in the chain.
+    pub fn contains(&self, name: &str) -> bool {
+        self.filters.iter().any(|node| node.name == name)
+    }
+}
+
+impl Extend<FilterNode> for FilterChain {
+    fn extend<I: IntoIterator<Item = FilterNode>>(&mut self, iter: I) {
+        self.filters.extend(iter);
+    }
+}
+
+impl fmt::Display for FilterChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for label in &self.inputs {
+            write!(f, "[{label}]")?;
+        }
+        for (i, node) in self.filters.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{node}")?;
+        }
+        for label in &self.outputs {
+            write!(f, "[{label}]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Chains separated by `;`, as passed to `-filter_complex` (or `-vf`/`-af`
+/// when the first input and last output are left unlabeled).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilterGraph {
+    chains: Vec<FilterChain>,
+}
+
+impl FilterGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, chain: FilterChain) -> Self {
+        self.push(chain);
+        self
+    }
+
+    /// Adds `chain` unless it has no filters.
+    pub fn push(&mut self, chain: FilterChain) {
+        if !chain.is_empty() {
+            self.chains.push(chain);
+        }
+    }
+
+    /// Adds all chains of `other` as separate chains.
+    pub fn extend(&mut self, other: FilterGraph) {
+        self.chains.extend(other.chains);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    /// Feeds this graph's unlabeled output into `tail`'s unlabeled input:
+    /// the last chain here and the first chain of `tail` become one chain.
+    pub fn then(mut self, tail: FilterGraph) -> Self {
+        let mut rest = tail.chains.into_iter();
+        match (self.chains.last_mut(), rest.next()) {
+            (Some(last), Some(first)) => {
+                last.filters.extend(first.filters);
+                last.outputs = first.outputs;
+            }
+            (None, Some(first)) => self.chains.push(first),
+            (_, None) => {}
+        }
+        self.chains.extend(rest);
+        self
+    }
+
+    /// Labels the first chain's input and the last chain's output.
+    /// An empty graph becomes a `null_filter` passthrough.
+    pub fn labeled(mut self, input: &str, output: &str, null_filter: &str) -> Self {
+        if self.chains.is_empty() {
+            self.chains
+                .push(FilterChain::new().then(FilterNode::new(null_filter)));
+        }
+        self.chains[0].inputs = vec![input.to_string()];
+        if let Some(last) = self.chains.last_mut() {
+            last.outputs = vec![output.to_string()];
+        }
+        self
+    }
+}
+
+impl From<FilterChain> for FilterGraph {
+    fn from(chain: FilterChain) -> Self {
+        FilterGraph::new().with(chain)
+    }
+}
+
+impl fmt::Display for FilterGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, chain) in self.chains.iter().enumerate() {
+            if i > 0 {
+                f.write_str(";")?;
+            }
+            write!(f, "{chain}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_filter_value() {
+        assert_eq!(escape_filter_value("/tmp/ssim.log"), "/tmp/ssim.log");
+        assert_eq!(
+            escape_filter_value(r"C:\tmp\a.log"),
+            r"C\\:\\\\tmp\\\\a.log"
+        );
+        assert_eq!(escape_filter_value("it's,x"), r"it\\\'s\,x");
+    }
+
+    #[test]
+    fn test_node_escapes_values() {
+        let node = FilterNode::new("psnr").opt("stats_file", "/tmp/a:b.log");
+        assert_eq!(node.to_string(), r"psnr=stats_file=/tmp/a\\:b.log");
+        let node = FilterNode::new("scale").arg(-2).arg(720);
+        assert_eq!(node.to_string(), "scale=-2:720");
+        assert_eq!(FilterNode::new("reverse").to_string(), "reverse");
+    }
+
+    #[test]
+    fn test_graph_with_labeled_pads() {
+        let graph = FilterGraph::new()
+            .with(
+                FilterChain::new()
+                    .input("0:v")
+                    .then(FilterNode::new("split").arg(2))
+                    .output("a")
+                    .output("b"),
+            )
+            .with(
+                FilterChain::new()
+                    .input("a")
+                    .input("b")
+                    .then(FilterNode::new("hstack"))
+                    .output("vout"),
+            );
+        assert_eq!(graph.to_string(), "[0:v]split=2[a][b];[a][b]hstack[vout]");
+    }
+
+    #[test]
+    fn test_then_joins_unlabeled_ends() {
+        let head =
+            FilterGraph::from(FilterChain::new().then(FilterNode::new("scale").arg(-2).arg(720)));
+        let tail = FilterGraph::new()
+            .with(
+                FilterChain::new()
+                    .then(FilterNode::new("split"))
+                    .output("f")
+                    .output("r"),
+            )
+            .with(
+                FilterChain::new()
+                    .input("r")
+                    .then(FilterNode::new("reverse"))
+                    .output("rr"),
+            );
+        assert_eq!(
+            head.then(tail).labeled("0:v", "vout", "null").to_string(),
+            "[0:v]scale=-2:720,split[f][r];[r]reverse[vout]"
+        );
+        assert_eq!(
+            FilterGraph::new()
+                .labeled("0:v", "vout", "null")
+                .to_string(),
+            "[0:v]null[vout]"
+        );
+    }
+}
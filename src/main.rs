@@ -1,52 +1,748 @@
+mod aspect;
+mod cancel;
 mod cli;
+mod color;
+mod encoder;
+mod error;
 mod ffmpeg;
 mod filters;
+mod framerate;
+mod graph;
+mod probe;
 mod progress;
+mod quality;
+mod report;
+mod scale;
+mod segments;
 mod tui;
+mod verify;
 
-use crate::cli::Cli;
-use crate::filters::{build_audio_filters, build_video_filters};
+use crate::cli::{AppConfig, Cli, ExistingOutput};
+use crate::error::{EnhancerError, FailureKind};
+use crate::ffmpeg::{Encode, Pass, RateControl, StagedOutput};
+use crate::filters::{
+    audio_filter_chain, build_audio_filters, build_reference_graph, build_segment_graph,
+    estimate_reverse_bytes, user_chain, video_filter_chain, with_user_filters, Direction,
+    FilterPlan, AUDIO_REENCODE, AUDIO_REENCODE_KBPS,
+};
+use crate::graph::{FilterChain, FilterGraph};
+use crate::probe::{MediaInfo, VideoInfo};
+use crate::progress::{Event, ProgressFormat};
+use crate::quality::QualityTarget;
+use crate::segments::Segment;
 use anyhow::Result;
 use clap::Parser;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-fn main() -> Result<()> {
-    let config = if std::env::args_os().len() > 1 {
+/// Exit codes are documented on `EnhancerError`.
+fn main() -> ExitCode {
+    let (config, format) = if std::env::args_os().len() > 1 {
         let cli = Cli::parse();
-        cli.into_config()?
+        let format = cli.progress_format;
+        let config = cli
+            .into_config()
+            .map_err(|e| EnhancerError::InvalidArgs(format!("{e:#}")).into());
+        (config, format)
     } else {
-        tui::interactive_config()?
+        (tui::interactive_config(), ProgressFormat::default())
     };
-    let tools = ffmpeg::resolve_tools(config.ffmpeg.clone(), config.ffprobe.clone())?;
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => return report_failure(format, &err),
+    };
+    let format = config.progress_format;
+    if let Err(err) = cancel::install() {
+        progress::note(format, &format!("Ctrl-C handler unavailable: {err}"));
+    }
+    match run(config) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => report_failure(format, &err),
+    }
+}
+
+/// Emits the `error` event (for `--progress-format json`), then fails.
+fn report_failure(format: ProgressFormat, err: &anyhow::Error) -> ExitCode {
+    progress::emit(
+        format,
+        &Event::Error {
+            message: &format!("{err:#}"),
+            exit_code: error::exit_code(err),
+        },
+    );
+    fail(err)
+}
+
+fn fail(err: &anyhow::Error) -> ExitCode {
+    eprintln!("Error: {err:?}");
+    ExitCode::from(error::exit_code(err))
+}
+
+fn run(mut config: AppConfig) -> Result<()> {
+    let started = Instant::now();
+    if config.output.exists() {
+        match config.existing {
+            ExistingOutput::Overwrite => {}
+            ExistingOutput::Fail => {
+                return Err(EnhancerError::InvalidArgs(format!(
+                    "{} already exists (--no-overwrite)",
+                    config.output.display()
+                ))
+                .into());
+            }
+            ExistingOutput::Skip => {
+                progress::note(
+                    config.progress_format,
+                    &format!("Skipping: {} already exists", config.output.display()),
+                );
+                return Ok(());
+            }
+        }
+    }
+    let tools = ffmpeg::resolve_tools(
+        config.ffmpeg.clone(),
+        config.ffprobe.clone(),
+        config.encoder.encoder,
+    )?;
+
+    let media = ffmpeg::probe_media(&tools, &config.input)?;
+    progress::emit(
+        config.progress_format,
+        &Event::Probe {
+            input: &config.input,
+            duration_s: media.duration,
+            width: media.video.as_ref().map(|v| v.width),
+            height: media.video.as_ref().map(|v| v.height),
+            has_audio: media.audio.is_some(),
+        },
+    );
+    let duration = media.duration;
+    // Frames as scaling sees them: auto-rotated, then through --vf-pre.
+    let source_frame = media
+        .video
+        .as_ref()
+        .map(|video| pre_filtered_size(&tools, &config, video));
+    if let (Some(video), Some((w, h))) = (&media.video, source_frame) {
+        config.scale.resolve(w, h);
+        let (w, h) = config.scale.frame().unwrap_or((w, h));
+        config.aspect.resolve(w, h);
+        if let Some(warning) = config.frame_rate.resolve(video) {
+            progress::note(config.progress_format, &warning);
+        }
+    }
+    check_reverse_memory(&config, &media)?;
+    let out_frame = output_frame(&config).or(source_frame);
+    if let (Some(video), Some((_, out_height))) = (&media.video, out_frame) {
+        let reencoded = reencodes_video(&config);
+        let note = config
+            .color
+            .resolve(video, out_height, reencoded)
+            .map_err(EnhancerError::InvalidArgs)?;
+        if let Some(note) = note {
+            progress::note(config.progress_format, &note);
+        }
+    }
+    if config.color.tonemapped.is_some() && !ffmpeg::has_filter(&tools, "zscale") {
+        return Err(EnhancerError::ToolNotFound {
+            tool: "zscale filter".to_string(),
+            detail: "HDR tone mapping needs an ffmpeg built with libzimg; \
+                     pass --tonemap off to keep HDR"
+                .to_string(),
+        }
+        .into());
+    }
+    // Recorded in the config so the dry run and report show the format used.
+    // Tone-mapped SDR defaults to 8-bit whatever the HDR source used.
+    let source_pix_fmt = match (&media.video, config.color.tonemapped) {
+        (Some(_), Some(_)) => Some("yuv420p"),
+        (video, None) => video.as_ref().map(|v| v.pix_fmt.as_str()),
+        (None, Some(_)) => None,
+    };
+    // eq works on 8-bit frames, so deeper output would only carry padding.
+    let uses_eq = video_filter_chain(
+        1.0,
+        None,
+        None,
+        None,
+        config.contrast,
+        config.saturation,
+        config.brightness,
+    )
+    .contains("eq");
+    let source_pix_fmt = match source_pix_fmt {
+        Some(source) if uses_eq => match config.encoder.eight_bit_source(source) {
+            Some(reduced) => {
+                progress::note(
+                    config.progress_format,
+                    &format!(
+                        "--brightness/--contrast/--saturation work in 8 bits; \
+                         encoding {reduced} rather than {source} (pass --bit-depth to override)"
+                    ),
+                );
+                Some(reduced)
+            }
+            None => Some(source),
+        },
+        other => other,
+    };
+    let pix_fmt = config
+        .encoder
+        .resolve_pix_fmt(source_pix_fmt, tools.encoder_pix_fmts.as_deref())
+        .map_err(EnhancerError::InvalidArgs)?;
+    config.encoder.pix_fmt = Some(pix_fmt.to_string());
+
+    let post_size = check_user_filters(&tools, &config, &media, out_frame)?;
+
+    let segments = plan_segments(&tools, &config, &media)?;
+    let (plan, audio_codec_when_touch, total_ms) = if let Some(segments) = &segments {
+        let has_audio = media.audio.is_some();
+        let post_video = video_graph(&config, 1.0)
+            .then(FilterGraph::from(user_chain(config.vf_post.as_deref())))
+            .then(config.direction.video_graph());
+        let plan = FilterPlan::Graph {
+            graph: build_segment_graph(
+                segments,
+                user_chain(config.vf_pre.as_deref()),
+                user_chain(config.af_pre.as_deref()),
+                post_video,
+                FilterGraph::from(user_chain(config.af_post.as_deref()))
+                    .then(config.direction.audio_graph()),
+                has_audio,
+            ),
+            audio: has_audio,
+        };
+        let total_ms = segments::planned_duration_ms(segments);
+        (plan, Vec::new(), total_ms)
+    } else {
+        let video_filters = video_graph(&config, config.speed);
+        let video_filters = with_user_filters(
+            config.vf_pre.as_deref(),
+            video_filters,
+            config.vf_post.as_deref(),
+        );
+        let (mut audio_filters_opt, mut audio_codec_when_touch) = build_audio_filters(config.speed);
+        let audio_tail = config.direction.audio_graph();
+        if !audio_tail.is_empty() || config.af_pre.is_some() || config.af_post.is_some() {
+            let audio = with_user_filters(
+                config.af_pre.as_deref(),
+                FilterGraph::from(audio_filter_chain(config.speed)),
+                config.af_post.as_deref(),
+            );
+            audio_filters_opt = Some(audio.then(audio_tail).to_string());
+            audio_codec_when_touch = AUDIO_REENCODE.to_vec();
+        }
+        let plan = FilterPlan::Chains {
+            video: video_filters
+                .then(config.direction.video_graph())
+                .to_string(),
+            audio: audio_filters_opt,
+        };
+        let total_ms = crate::cli::target_duration_ms(duration, config.speed);
+        (plan, audio_codec_when_touch, total_ms)
+    };
+
+    let total_ms = total_ms * config.direction.duration_factor();
+    let mut staged = StagedOutput::new(&config.output);
+
+    if config.dry_run {
+        return print_dry_run(
+            &tools,
+            &config,
+            &media,
+            &plan,
+            &audio_codec_when_touch,
+            total_ms,
+        );
+    }
+
+    if let Some(target) = config.target_size {
+        let kbps = target_kbps(&plan, &media, target, total_ms)?;
+        let passlog = ffmpeg::PassLog::new();
+        for (n, pass) in [
+            (1, Pass::First(passlog.prefix.clone())),
+            (2, Pass::Second(passlog.prefix.clone())),
+        ] {
+            let encode = Encode {
+                rate: RateControl::Bitrate(kbps),
+                pass,
+                force_reencode: false,
+            };
+            let mut ui =
+                progress::ProgressUi::new(total_ms, plan.touches_audio(), config.progress_format);
+            ui.set_pass(n, 2);
+            run_encode(
+                &tools,
+                &config,
+                &plan,
+                &audio_codec_when_touch,
+                &encode,
+                &mut staged,
+                ui,
+            )?;
+        }
+        config.rate_control = Some(RateControl::Bitrate(kbps));
+        let written = std::fs::metadata(&staged.path)?.len();
+        if written > target {
+            return Err(EnhancerError::Verification(format!(
+                "output is {written} bytes, above --target-size {target} bytes; \
+                 try a smaller --scale or a lower target"
+            ))
+            .into());
+        }
+    } else {
+        if let Some(target) = config.quality_target {
+            // Recorded in the config so the report shows the CRF actually used.
+            config.crf = choose_crf(&tools, &config, &media, &plan, target)?;
+        }
+        let mut encode = Encode {
+            rate: RateControl::Crf(config.crf),
+            pass: Pass::Single,
+            force_reencode: false,
+        };
+        let ui = progress::ProgressUi::new(total_ms, plan.touches_audio(), config.progress_format);
+        let result = run_encode(
+            &tools,
+            &config,
+            &plan,
+            &audio_codec_when_touch,
+            &encode,
+            &mut staged,
+            ui,
+        );
+        match copy_failure(&config, &plan, &encode, result)? {
+            None => {}
+            Some(kind) => {
+                progress::note(
+                    config.progress_format,
+                    &format!(
+                        "Stream copy failed ({}); retrying with a re-encode (--fallback)",
+                        kind.summary()
+                    ),
+                );
+                encode.force_reencode = true;
+                let ui = progress::ProgressUi::new(total_ms, true, config.progress_format);
+                run_encode(
+                    &tools,
+                    &config,
+                    &plan,
+                    &audio_codec_when_touch,
+                    &encode,
+                    &mut staged,
+                    ui,
+                )?;
+            }
+        }
+        config.rate_control = (!encode.copies_video(&plan)).then_some(encode.rate);
+    }
+    let expected = verify::Expected {
+        duration_ms: total_ms,
+        video: media.video.is_some(),
+        audio: media.audio.is_some(),
+        // --vf-post may resize too; unchecked when its size couldn't be measured.
+        size: match &config.vf_post {
+            Some(_) => post_size,
+            None => output_frame(&config),
+        },
+    };
+    verify::verify_output(&tools, &staged.path, &expected, config.verify_decode)?;
+    staged.commit()?;
+
+    if let Some(report_path) = &config.report {
+        // --vf-pre usually crops, so the reference gets it too.
+        let mut pre = user_chain(config.vf_pre.as_deref());
+        pre.extend(
+            config
+                .color
+                .tonemap_filters(config.encoder.pix_fmt.as_deref().unwrap_or("yuv420p")),
+        );
+        let mut scaled = FilterChain::new();
+        scaled.extend(config.scale.filters());
+        let reference = build_reference_graph(
+            config.speed,
+            segments.as_deref(),
+            pre,
+            FilterGraph::from(scaled).then(config.aspect.graph()),
+            config.frame_rate.filter(),
+            config.direction,
+        );
+        report::write_report(&tools, &config, &reference, report_path)?;
+    }
+
+    progress::emit(
+        config.progress_format,
+        &Event::Done {
+            output: &config.output,
+            bytes: std::fs::metadata(&config.output)?.len(),
+            elapsed_s: started.elapsed().as_secs_f64(),
+        },
+    );
+    Ok(())
+}
+
+/// Video bitrate for --target-size after the audio's share.
+fn target_kbps(plan: &FilterPlan, media: &MediaInfo, target: u64, total_ms: u64) -> Result<u32> {
+    let audio_kbps = if plan.touches_audio() {
+        AUDIO_REENCODE_KBPS
+    } else {
+        media.audio.as_ref().map_or(0, |a| match a.bit_rate {
+            0 => AUDIO_REENCODE_KBPS,
+            bps => (bps / 1000) as u32,
+        })
+    };
+    ffmpeg::video_kbps_for_size(target, total_ms, audio_kbps)
+}
+
+/// --dry-run: shows the filter breakdown and the ffmpeg command(s) that
+/// would write the output, without running them.
+fn print_dry_run(
+    tools: &ffmpeg::Tools,
+    config: &AppConfig,
+    media: &MediaInfo,
+    plan: &FilterPlan,
+    audio_codec: &[&str],
+    total_ms: u64,
+) -> Result<()> {
+    let encodes = if let Some(target) = config.target_size {
+        let kbps = target_kbps(plan, media, target, total_ms)?;
+        let prefix = ffmpeg::PassLog::new().prefix.clone();
+        vec![
+            Encode {
+                rate: RateControl::Bitrate(kbps),
+                pass: Pass::First(prefix.clone()),
+                force_reencode: false,
+            },
+            Encode {
+                rate: RateControl::Bitrate(kbps),
+                pass: Pass::Second(prefix),
+                force_reencode: false,
+            },
+        ]
+    } else {
+        if config.quality_target.is_some() {
+            progress::note(
+                config.progress_format,
+                &format!("Dry run: CRF search skipped; showing --crf {}", config.crf),
+            );
+        }
+        vec![Encode {
+            rate: RateControl::Crf(config.crf),
+            pass: Pass::Single,
+            force_reencode: false,
+        }]
+    };
+
+    let breakdown = plan.describe(encodes[0].copies_video(plan));
+    for line in breakdown.lines() {
+        progress::note(config.progress_format, line);
+    }
+    for encode in &encodes {
+        let cmd = ffmpeg::encode_command(tools, config, plan, audio_codec, encode, &config.output);
+        progress::note(config.progress_format, &ffmpeg::quote_command(&cmd));
+    }
+    Ok(())
+}
 
-    let duration = ffmpeg::probe_duration_seconds(&tools, &config.input)?;
-    let total_ms = crate::cli::target_duration_ms(duration, config.speed);
+/// Output frame size after scaling and reframing, when it differs from the source.
+fn output_frame(config: &AppConfig) -> Option<(u32, u32)> {
+    config.aspect.frame().or(config.scale.frame())
+}
+
+/// Size of the decoded (auto-rotated) frames after --vf-pre, which may crop
+/// or pad; measured on a test clip when --vf-pre is set.
+fn pre_filtered_size(tools: &ffmpeg::Tools, config: &AppConfig, video: &VideoInfo) -> (u32, u32) {
+    let (width, height) = video.display_size();
+    let Some(filters) = &config.vf_pre else {
+        return (width, height);
+    };
+    let input = ffmpeg::FilterInput::Video {
+        width,
+        height,
+        fps: video.avg_frame_rate,
+        pix_fmt: &video.pix_fmt,
+    };
+    ffmpeg::filtered_size(tools, &input, filters).unwrap_or((width, height))
+}
 
-    let video_filters = build_video_filters(
-        config.speed,
+/// The generated per-picture filters (tone mapping, enhancements, scaling,
+/// reframing, colour conversion) with the speed change and frame rate
+/// conversion. Tone mapping comes first so the `eq` knobs work on SDR.
+fn video_graph(config: &AppConfig, speed: f64) -> FilterGraph {
+    let pix_fmt = config.encoder.pix_fmt.as_deref().unwrap_or("yuv420p");
+    let mut chain = FilterChain::new();
+    chain.extend(config.color.tonemap_filters(pix_fmt));
+    let chain = chain.append(video_filter_chain(
+        speed,
         config.denoise,
-        config.scale,
+        Some(&config.scale),
         config.sharpen,
         config.contrast,
         config.saturation,
         config.brightness,
+    ));
+    let mut tail = FilterChain::new();
+    tail.extend(config.color.filter());
+    // After retiming, so the output rate is what --fps asked for.
+    tail.extend(config.frame_rate.filter());
+    FilterGraph::from(chain)
+        .then(config.aspect.graph())
+        .then(FilterGraph::from(tail))
+}
+
+/// Whether the video is re-encoded whatever the colour handling adds: it is
+/// filtered, retimed by segment or encoded to a size. Called before colour is
+/// resolved, so `video_graph` holds no tone mapping yet.
+fn reencodes_video(config: &AppConfig) -> bool {
+    config.target_size.is_some()
+        || config.speed_map.is_some()
+        || config.variable_speed.is_some()
+        || config.vf_pre.is_some()
+        || config.vf_post.is_some()
+        || !video_graph(config, config.speed)
+            .then(config.direction.video_graph())
+            .is_empty()
+}
+
+/// Runs the sample-based CRF search unless the video is stream-copied anyway.
+fn choose_crf(
+    tools: &ffmpeg::Tools,
+    config: &AppConfig,
+    media: &MediaInfo,
+    plan: &FilterPlan,
+    target: QualityTarget,
+) -> Result<u8> {
+    if let FilterPlan::Chains { video, .. } = plan
+        && video.is_empty()
+    {
+        progress::note(
+            config.progress_format,
+            "Video is stream-copied (no quality loss); skipping CRF search.",
+        );
+        return Ok(config.crf);
+    }
+    // Samples are compared frame by frame, so leave out retiming and reversal.
+    let per_frame = video_graph(config, 1.0);
+    let per_frame = with_user_filters(
+        config.vf_pre.as_deref(),
+        per_frame,
+        config.vf_post.as_deref(),
     );
-    let (audio_filters_opt, audio_codec_when_touch) = build_audio_filters(config.speed);
+    let choice = quality::run_crf_search(tools, config, media.duration, &per_frame, target)?;
+    let message = if choice.met {
+        format!(
+            "Chose CRF {} ({} {:.4} >= {})",
+            choice.crf,
+            target.filter_name(),
+            choice.score,
+            target.threshold()
+        )
+    } else {
+        format!(
+            "Target {target} not reached even at CRF {} ({} {:.4}); using it anyway",
+            choice.crf,
+            target.filter_name(),
+            choice.score
+        )
+    };
+    progress::note(config.progress_format, &message);
+    Ok(choice.crf)
+}
 
-    let ui = progress::ProgressUi::new(total_ms, audio_filters_opt.is_some());
+fn run_encode(
+    tools: &ffmpeg::Tools,
+    config: &AppConfig,
+    plan: &FilterPlan,
+    audio_codec: &[&str],
+    encode: &Encode,
+    staged: &mut StagedOutput,
+    ui: progress::ProgressUi,
+) -> Result<()> {
+    if cancel::requested() {
+        return Err(EnhancerError::Cancelled.into());
+    }
+    let cmd = ffmpeg::encode_command(tools, config, plan, audio_codec, encode, &staged.path);
+    if config.print_command {
+        progress::note(config.progress_format, &ffmpeg::quote_command(&cmd));
+    }
+    let session = ffmpeg::spawn_ffmpeg(cmd, config.verbose)?;
+    progress::emit(
+        config.progress_format,
+        &Event::Start {
+            output: &config.output,
+            total_ms: ui.total_ms(),
+        },
+    );
 
-    let session = ffmpeg::spawn_ffmpeg(
-        &tools,
-        &config,
-        &video_filters,
-        audio_filters_opt.as_deref(),
-        &audio_codec_when_touch,
-    )?;
+    let heartbeat = progress::Heartbeat::new();
+    let progress_handle = progress::pump_progress(session.stdout, ui, heartbeat.clone());
+    let waited = ffmpeg::wait_for_completion(
+        session.child,
+        session.stderr,
+        &heartbeat,
+        config.stall_timeout.map(Duration::from_secs),
+    );
+    progress_handle.join().expect("progress thread panicked")?;
+    if let Err(err) = &waited
+        && matches!(err.downcast_ref(), Some(EnhancerError::Cancelled))
+    {
+        discard_partial(config, encode, staged);
+    }
+    waited
+}
 
-    let progress_handle = progress::pump_progress(session.stdout, ui);
-    ffmpeg::wait_for_completion(session.child)?;
-    progress_handle
-        .join()
-        .expect("progress thread panicked")?;
+/// A cancelled encode leaves a file without its index. The staged file is
+/// removed on drop unless --keep-partial asks to keep it. Analysis passes
+/// never write it.
+fn discard_partial(config: &AppConfig, encode: &Encode, staged: &mut StagedOutput) {
+    if matches!(encode.pass, Pass::First(_)) || !staged.path.exists() {
+        return;
+    }
+    let message = if config.keep_partial {
+        staged.keep();
+        format!("Kept partial output {}", staged.path.display())
+    } else {
+        match staged.discard() {
+            Ok(()) => "Removed partial output".to_string(),
+            Err(err) => format!(
+                "Could not remove partial output {}: {err}",
+                staged.path.display()
+            ),
+        }
+    };
+    progress::note(config.progress_format, &message);
+}
+
+/// With --fallback, a stream-copy encode that failed for a reason a
+/// re-encode fixes yields that reason; every other outcome passes through.
+fn copy_failure(
+    config: &AppConfig,
+    plan: &FilterPlan,
+    encode: &Encode,
+    result: Result<()>,
+) -> Result<Option<FailureKind>> {
+    let Err(err) = result else {
+        return Ok(None);
+    };
+    if config.fallback
+        && encode.copies_video(plan)
+        && let Some(EnhancerError::Encode(failure)) = err.downcast_ref()
+        && failure.kind.reencode_helps()
+    {
+        return Ok(Some(failure.kind));
+    }
+    Err(err)
+}
+
+/// Per-segment speeds from --speed-map or silence detection; `None` means one
+/// uniform speed for the whole file.
+fn plan_segments(
+    tools: &ffmpeg::Tools,
+    config: &AppConfig,
+    media: &MediaInfo,
+) -> Result<Option<Vec<Segment>>> {
+    let duration = media.duration;
+    if let Some(map) = &config.speed_map {
+        return Ok(Some(segments::segments_from_keyframes(
+            duration,
+            map,
+            config.speed,
+        )));
+    }
+    if let Some(vs) = &config.variable_speed {
+        let silences = if media.audio.is_some() {
+            ffmpeg::detect_silence(tools, &config.input, vs, duration)?
+        } else {
+            Vec::new()
+        };
+        return Ok(Some(segments::segments_from_silence(
+            duration, &silences, vs.speech, vs.silence,
+        )));
+    }
+    Ok(None)
+}
+
+/// Parse-checks --vf-pre/--vf-post/--af-pre/--af-post before anything runs,
+/// on test clips shaped like what each chain will actually receive.
+/// `out_frame` is the frame size --vf-post gets; returns the size it makes
+/// of it, when --vf-post is set and the test clip could be measured.
+fn check_user_filters(
+    tools: &ffmpeg::Tools,
+    config: &AppConfig,
+    media: &MediaInfo,
+    out_frame: Option<(u32, u32)>,
+) -> Result<Option<(u32, u32)>> {
+    let (source, fps, source_pix_fmt) = match &media.video {
+        Some(v) if v.width > 0 && v.height > 0 => {
+            (v.display_size(), v.avg_frame_rate, v.pix_fmt.as_str())
+        }
+        _ => ((320, 240), 25.0, ""),
+    };
+    // Tone mapping converts to the output format before --vf-post.
+    let post_pix_fmt = match config.color.tonemapped {
+        Some(_) => config.encoder.pix_fmt.as_deref().unwrap_or(source_pix_fmt),
+        None => source_pix_fmt,
+    };
+    let video = |(width, height), pix_fmt| ffmpeg::FilterInput::Video {
+        width,
+        height,
+        fps,
+        pix_fmt,
+    };
+    let audio_info = media.audio.as_ref();
+    let audio = ffmpeg::FilterInput::Audio {
+        sample_rate: audio_info
+            .map(|a| a.sample_rate)
+            .filter(|&r| r > 0)
+            .unwrap_or(48_000),
+        channels: audio_info
+            .map(|a| a.channels)
+            .filter(|&c| c > 0)
+            .unwrap_or(2),
+    };
+    let post_frame = out_frame.unwrap_or(source);
+    for (flag, filters, input) in [
+        ("vf-pre", &config.vf_pre, video(source, source_pix_fmt)),
+        ("vf-post", &config.vf_post, video(post_frame, post_pix_fmt)),
+        ("af-pre", &config.af_pre, audio.clone()),
+        ("af-post", &config.af_post, audio),
+    ] {
+        if let Some(filters) = filters {
+            ffmpeg::check_user_filters(tools, flag, filters, &input)?;
+        }
+    }
+    let post_size = config.vf_post.as_ref().and_then(|filters| {
+        ffmpeg::filtered_size(tools, &video(post_frame, post_pix_fmt), filters)
+    });
+    // The --report reference is reframed like the output up to --vf-post,
+    // so a --vf-post that resizes would leave nothing to compare with.
+    if config.report.is_some()
+        && let Some((w, h)) = post_size
+        && (w, h) != post_frame
+    {
+        return Err(EnhancerError::InvalidArgs(format!(
+            "--report can't compare with the source when --vf-post changes the frame size \
+             ({}x{} to {w}x{h}); move the resize to --vf-pre",
+            post_frame.0, post_frame.1
+        ))
+        .into());
+    }
+    Ok(post_size)
+}
 
+/// reverse/areverse keep the whole clip in memory, so refuse jobs that would
+/// not fit instead of letting ffmpeg swap or get OOM-killed.
+fn check_reverse_memory(config: &AppConfig, media: &MediaInfo) -> Result<()> {
+    if config.direction == Direction::Forward {
+        return Ok(());
+    }
+    let needed = estimate_reverse_bytes(media, output_frame(config), config.frame_rate.rate());
+    if needed > config.reverse_memory_limit {
+        return Err(EnhancerError::InvalidArgs(format!(
+            "Reversing this clip needs about {:.1} MiB of memory, above the {:.1} MiB limit. \
+             Trim or downscale the input (--scale), or raise --reverse-memory-limit.",
+            needed as f64 / (1024.0 * 1024.0),
+            config.reverse_memory_limit as f64 / (1024.0 * 1024.0)
+        ))
+        .into());
+    }
     Ok(())
 }
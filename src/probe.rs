@@ -0,0 +1,213 @@
+use crate::color::ColorTags;
+
+/// What ffprobe told us about the input, parsed from `-of flat` output.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    pub duration: f64,
+    pub video: Option<VideoInfo>,
+    pub audio: Option<AudioInfo>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VideoInfo {
+    pub width: u32,
+    pub height: u32,
+    pub avg_frame_rate: f64,
+    /// Nominal rate; differs from `avg_frame_rate` for variable frame rate.
+    pub r_frame_rate: f64,
+    /// e.g. `yuv420p10le`; empty when ffprobe doesn't report it.
+    pub pix_fmt: String,
+    pub color: ColorTags,
+    /// Degrees from the display matrix (or the older `rotate` tag). ffmpeg
+    /// rotates frames while decoding, so quarter turns swap width and height.
+    pub rotation: i32,
+}
+
+impl VideoInfo {
+    /// Size of the frames ffmpeg decodes, after auto-rotation.
+    pub fn display_size(&self) -> (u32, u32) {
+        if self.rotation.rem_euclid(180) == 90 {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AudioInfo {
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// Bits per second; 0 when the container doesn't say.
+    pub bit_rate: u64,
+}
+
+/// `-show_entries` selection matching what `parse_flat` understands.
+pub const PROBE_ENTRIES: &str =
+    "format=duration:stream=codec_type,width,height,avg_frame_rate,r_frame_rate,pix_fmt,\
+color_primaries,color_transfer,color_space,color_range,sample_rate,channels,bit_rate:\
+stream_tags=rotate:stream_side_data=rotation";
+
+/// Parses ffprobe `-of flat` lines such as `streams.stream.0.width=1920`.
+/// Only the first video and first audio stream are kept.
+pub fn parse_flat(text: &str) -> Result<MediaInfo, String> {
+    let mut info = MediaInfo::default();
+    let mut streams: Vec<(String, Vec<(String, String)>)> = Vec::new();
+
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"').to_string();
+        if key == "format.duration" {
+            info.duration = value
+                .parse()
+                .map_err(|_| format!("cannot parse duration `{value}`"))?;
+            continue;
+        }
+        let Some(rest) = key.strip_prefix("streams.stream.") else {
+            continue;
+        };
+        let Some((idx, field)) = rest.split_once('.') else {
+            continue;
+        };
+        let Ok(idx) = idx.parse::<usize>() else {
+            continue;
+        };
+        if streams.len() <= idx {
+            streams.resize(idx + 1, (String::new(), Vec::new()));
+        }
+        if field == "codec_type" {
+            streams[idx].0 = value;
+        } else {
+            streams[idx].1.push((field.to_string(), value));
+        }
+    }
+
+    for (kind, fields) in streams {
+        let get = |name: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+                .unwrap_or("")
+        };
+        match kind.as_str() {
+            "video" if info.video.is_none() => {
+                let rotation = fields
+                    .iter()
+                    .find(|(k, _)| k.starts_with("side_data_list.") && k.ends_with(".rotation"))
+                    .map_or_else(|| get("tags.rotate"), |(_, v)| v.as_str());
+                info.video = Some(VideoInfo {
+                    width: get("width").parse().unwrap_or(0),
+                    height: get("height").parse().unwrap_or(0),
+                    avg_frame_rate: parse_rational(get("avg_frame_rate")).unwrap_or(0.0),
+                    r_frame_rate: parse_rational(get("r_frame_rate")).unwrap_or(0.0),
+                    pix_fmt: get("pix_fmt").to_string(),
+                    color: ColorTags::new(
+                        get("color_primaries"),
+                        get("color_transfer"),
+                        get("color_space"),
+                        get("color_range"),
+                    ),
+                    rotation: rotation.parse::<f64>().map_or(0, |r| r.round() as i32),
+                });
+            }
+            "audio" if info.audio.is_none() => {
+                info.audio = Some(AudioInfo {
+                    sample_rate: get("sample_rate").parse().unwrap_or(0),
+                    channels: get("channels").parse().unwrap_or(0),
+                    bit_rate: get("bit_rate").parse().unwrap_or(0),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    if info.duration <= 0.0 {
+        return Err("input has no usable duration".into());
+    }
+    Ok(info)
+}
+
+/// Parses ffprobe rationals like `30000/1001`; `0/0` yields `None`.
+pub fn parse_rational(raw: &str) -> Option<f64> {
+    let (num, den) = raw.split_once('/').unwrap_or((raw, "1"));
+    let num: f64 = num.trim().parse().ok()?;
+    let den: f64 = den.trim().parse().ok()?;
+    if den == 0.0 || num == 0.0 {
+        return None;
+    }
+    Some(num / den)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAT: &str = r#"streams.stream.0.codec_type="video"
+streams.stream.0.width=1920
+streams.stream.0.height=1080
+streams.stream.0.avg_frame_rate="30000/1001"
+streams.stream.0.r_frame_rate="60/1"
+streams.stream.0.pix_fmt="yuv420p10le"
+streams.stream.0.color_space="bt709"
+streams.stream.0.color_primaries="unknown"
+streams.stream.1.codec_type="audio"
+streams.stream.1.sample_rate="48000"
+streams.stream.1.channels=2
+streams.stream.1.bit_rate="128000"
+format.duration="12.500000"
+"#;
+
+    #[test]
+    fn test_parse_flat() {
+        let info = parse_flat(FLAT).unwrap();
+        assert_eq!(info.duration, 12.5);
+        let v = info.video.unwrap();
+        assert_eq!((v.width, v.height), (1920, 1080));
+        assert!((v.avg_frame_rate - 29.97).abs() < 0.01);
+        assert_eq!(v.r_frame_rate, 60.0);
+        assert_eq!(v.pix_fmt, "yuv420p10le");
+        assert_eq!(v.color.matrix.as_deref(), Some("bt709"));
+        assert_eq!(v.color.primaries, None);
+        let a = info.audio.unwrap();
+        assert_eq!((a.sample_rate, a.channels, a.bit_rate), (48000, 2, 128_000));
+    }
+
+    #[test]
+    fn test_rotation_swaps_display_size() {
+        let phone = "streams.stream.0.codec_type=\"video\"
+streams.stream.0.width=1920
+streams.stream.0.height=1080
+streams.stream.0.side_data_list.side_data.0.rotation=-90
+format.duration=\"3\"
+";
+        let v = parse_flat(phone).unwrap().video.unwrap();
+        assert_eq!(v.rotation, -90);
+        assert_eq!(v.display_size(), (1080, 1920));
+
+        let old_tag = phone.replace(
+            "side_data_list.side_data.0.rotation=-90",
+            "tags.rotate=\"180\"",
+        );
+        let v = parse_flat(&old_tag).unwrap().video.unwrap();
+        assert_eq!(v.display_size(), (1920, 1080));
+    }
+
+    #[test]
+    fn test_parse_flat_without_audio() {
+        let info =
+            parse_flat("streams.stream.0.codec_type=\"video\"\nformat.duration=\"3\"\n").unwrap();
+        assert!(info.video.is_some());
+        assert!(info.audio.is_none());
+        assert!(parse_flat("format.duration=\"N/A\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_rational() {
+        assert_eq!(parse_rational("25/1"), Some(25.0));
+        assert_eq!(parse_rational("0/0"), None);
+        assert_eq!(parse_rational("24"), Some(24.0));
+    }
+}
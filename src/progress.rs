@@ -1,23 +1,143 @@
+use crate::cancel;
 use anyhow::Result;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use clap::ValueEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use regex::Regex;
-use std::io::{BufRead, BufReader, Read};
+use serde::Serialize;
+use std::io::{BufRead, BufReader, IsTerminal, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// How progress and lifecycle events reach the user or a wrapping process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressFormat {
+    /// Interactive bars (not drawn when stdout is not a terminal).
+    #[default]
+    Bar,
+    /// One human-readable line per update.
+    Plain,
+    /// One NDJSON object per update and lifecycle event.
+    Json,
+}
+
+/// Lifecycle and progress events. In `json` mode each is one line on stdout,
+/// tagged by `"event"`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Probe {
+        input: &'a Path,
+        duration_s: f64,
+        width: Option<u32>,
+        height: Option<u32>,
+        has_audio: bool,
+    },
+    Start {
+        output: &'a Path,
+        total_ms: u64,
+    },
+    Pass {
+        pass: u8,
+        of: u8,
+    },
+    Progress {
+        #[serde(flatten)]
+        snapshot: &'a ProgressSnapshot,
+        out_time_ms: u64,
+        total_ms: u64,
+        percent: f64,
+        eta_s: Option<u64>,
+        projected_size: Option<u64>,
+    },
+    Log {
+        message: &'a str,
+    },
+    Done {
+        output: &'a Path,
+        bytes: u64,
+        elapsed_s: f64,
+    },
+    Error {
+        message: &'a str,
+        exit_code: u8,
+    },
+}
+
+/// Writes a lifecycle event. Bars stay quiet except for log lines, which are
+/// printed as-is in both human formats.
+pub fn emit(format: ProgressFormat, event: &Event) {
+    match format {
+        ProgressFormat::Json => {
+            if let Ok(line) = serde_json::to_string(event) {
+                println!("{line}");
+            }
+        }
+        ProgressFormat::Plain => match event {
+            Event::Probe {
+                input, duration_s, ..
+            } => println!("probe: {} ({duration_s:.2}s)", input.display()),
+            Event::Start { output, total_ms } => {
+                println!("start: {} ({total_ms} ms of output)", output.display())
+            }
+            Event::Pass { pass, of } => println!("pass {pass}/{of}"),
+            Event::Progress {
+                snapshot,
+                percent,
+                eta_s,
+                ..
+            } => println!(
+                "progress {percent:5.1}%  frame={} fps={:.1} speed={} eta={}",
+                snapshot.frame,
+                snapshot.fps,
+                snapshot.speed.map_or("N/A".into(), |s| format!("{s:.2}x")),
+                eta_s.map_or("N/A".into(), format_hms)
+            ),
+            Event::Log { message } => println!("{message}"),
+            Event::Done { output, bytes, .. } => {
+                println!("done: {} ({})", output.display(), human_bytes(*bytes))
+            }
+            // The error itself is printed by main.
+            Event::Error { .. } => {}
+        },
+        ProgressFormat::Bar => {
+            if let Event::Log { message } = event {
+                println!("{message}");
+            }
+        }
+    }
+}
+
+/// Informational message routed through `emit` so JSON consumers get it as an event.
+pub fn note(format: ProgressFormat, message: &str) {
+    emit(format, &Event::Log { message });
+}
 
 pub struct ProgressUi {
     _multi: MultiProgress,
     spinner: ProgressBar,
     bar: ProgressBar,
     total_ms: u64,
+    pass_label: String,
+    format: ProgressFormat,
 }
 
 impl ProgressUi {
-    pub fn new(total_ms: u64, audio_time_stretch: bool) -> Self {
-        let multi = MultiProgress::new();
+    pub fn new(total_ms: u64, audio_time_stretch: bool, format: ProgressFormat) -> Self {
+        let draw = format == ProgressFormat::Bar && std::io::stdout().is_terminal();
+        let multi = MultiProgress::with_draw_target(if draw {
+            ProgressDrawTarget::stderr()
+        } else {
+            ProgressDrawTarget::hidden()
+        });
 
         let spinner = multi.add(ProgressBar::new_spinner());
-        spinner.enable_steady_tick(Duration::from_millis(80));
+        if draw {
+            spinner.enable_steady_tick(Duration::from_millis(80));
+        }
         spinner.set_style(
             ProgressStyle::with_template("{spinner} {msg}")
                 .unwrap()
@@ -28,7 +148,7 @@ impl ProgressUi {
         let bar = multi.add(ProgressBar::new(total_ms));
         bar.set_style(
             ProgressStyle::with_template(
-                "[{elapsed_precise}]  [{bar:60.cyan/bright-black}] {percent:>3}%  {pos}/{len}ms  ETA:{eta_precise}\n{wide_msg}"
+                "[{elapsed_precise}]  [{bar:60.cyan/bright-black}] {percent:>3}%  {pos}/{len}ms\n{wide_msg}"
             )
             .unwrap()
             .progress_chars("#>-"),
@@ -43,62 +163,331 @@ impl ProgressUi {
             spinner,
             bar,
             total_ms,
+            pass_label: String::new(),
+            format,
         }
     }
 
-    fn update_stage(&self, pos_ms: u64) {
+    pub fn total_ms(&self) -> u64 {
+        self.total_ms
+    }
+
+    /// Prefixes stage messages with "Pass n/of" for multi-pass encodes.
+    pub fn set_pass(&mut self, n: u8, of: u8) {
+        self.pass_label = format!("Pass {n}/{of}: ");
+        self.spinner
+            .set_message(format!("{}Preparing.", self.pass_label));
+        emit(self.format, &Event::Pass { pass: n, of });
+    }
+
+    fn set_stage(&self, msg: &str) {
+        self.spinner
+            .set_message(format!("{}{msg}", self.pass_label));
+    }
+
+    fn update(&self, snap: &ProgressSnapshot) {
+        let pos_ms = snap.out_time_ms().min(self.total_ms);
+        let eta = eta_seconds(self.total_ms, pos_ms, snap.speed);
+        let projected = projected_size(snap.total_size, pos_ms, self.total_ms);
+        if self.format != ProgressFormat::Bar {
+            emit(
+                self.format,
+                &Event::Progress {
+                    snapshot: snap,
+                    out_time_ms: pos_ms,
+                    total_ms: self.total_ms,
+                    percent: pos_ms as f64 * 100.0 / self.total_ms.max(1) as f64,
+                    eta_s: eta,
+                    projected_size: projected,
+                },
+            );
+            return;
+        }
+
         self.bar.set_position(pos_ms);
-        let pct = (pos_ms as f64) / (self.total_ms as f64);
-        if pct < 0.10 {
-            self.spinner.set_message("Preparing filters.");
-            self.bar
-                .set_message("Applying selected filters (if any)...");
-        } else if pct < 0.65 {
-            self.spinner.set_message("Encoding video.");
-            self.bar.set_message("Processing frames...");
-        } else if pct < 0.95 {
-            self.spinner.set_message("Adjusting/encoding audio.");
-            self.bar
-                .set_message("Applying atempo (if speed != 1.0)...");
-        } else {
-            self.spinner.set_message("Finalizing and muxing.");
-            self.bar
-                .set_message("Muxing, writing headers, closing output...");
+        if snap.frame == 0 && pos_ms == 0 {
+            self.set_stage("Waiting for first frames.");
+            return;
         }
+        self.set_stage(&format!(
+            "Encoding: frame {}  {:.1} fps  {}",
+            snap.frame,
+            snap.fps,
+            snap.speed.map_or("--x".to_string(), |s| format!("{s:.2}x"))
+        ));
+
+        let bitrate = snap
+            .bitrate_kbps
+            .map_or("-- kbit/s".to_string(), |b| format!("{b:.0} kbit/s"));
+        let size = match projected {
+            Some(p) => format!("{} -> ~{}", human_bytes(snap.total_size), human_bytes(p)),
+            None => human_bytes(snap.total_size),
+        };
+        let eta = eta.map_or("--:--:--".to_string(), format_hms);
+        let mut msg = format!("{bitrate}  {size}  ETA {eta}");
+        if snap.dup_frames > 0 || snap.drop_frames > 0 {
+            msg.push_str(&format!(
+                "  dup {} / drop {}",
+                snap.dup_frames, snap.drop_frames
+            ));
+        }
+        self.bar.set_message(msg);
+    }
+
+    fn abandon(&self, why: &str) {
+        self.bar.abandon_with_message(why.to_string());
+        self.spinner
+            .abandon_with_message(format!("{}{why}", self.pass_label));
     }
 
     fn finish(&self) {
         self.bar.finish_with_message("Done");
-        self.spinner.finish_with_message("Completed");
+        self.spinner
+            .finish_with_message(format!("{}Completed", self.pass_label));
+    }
+}
+
+/// Latest values from one ffmpeg `-progress` block.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProgressSnapshot {
+    pub frame: u64,
+    pub fps: f64,
+    pub bitrate_kbps: Option<f64>,
+    pub total_size: u64,
+    /// Encode speed as a multiple of real time (`speed=2.5x`).
+    pub speed: Option<f64>,
+    pub dup_frames: u64,
+    pub drop_frames: u64,
+    pub out_time_us: u64,
+}
+
+impl ProgressSnapshot {
+    /// Applies one `key=value` line; unknown keys and `N/A` values are ignored.
+    pub fn apply(&mut self, key: &str, val: &str) {
+        let val = val.trim();
+        match key {
+            "frame" => self.frame = val.parse().unwrap_or(self.frame),
+            "fps" => self.fps = val.parse().unwrap_or(self.fps),
+            "bitrate" => {
+                self.bitrate_kbps = val.trim_end_matches("kbits/s").trim().parse().ok();
+            }
+            "total_size" => self.total_size = val.parse().unwrap_or(self.total_size),
+            "speed" => self.speed = val.trim_end_matches('x').trim().parse().ok(),
+            "dup_frames" => self.dup_frames = val.parse().unwrap_or(self.dup_frames),
+            "drop_frames" => self.drop_frames = val.parse().unwrap_or(self.drop_frames),
+            // Despite the name, out_time_ms is also in microseconds.
+            "out_time_us" | "out_time_ms" => {
+                self.out_time_us = val.parse().unwrap_or(self.out_time_us)
+            }
+            _ => {}
+        }
+    }
+
+    pub fn out_time_ms(&self) -> u64 {
+        self.out_time_us / 1000
+    }
+}
+
+/// Remaining media time divided by the current encode speed.
+pub fn eta_seconds(total_ms: u64, pos_ms: u64, speed: Option<f64>) -> Option<u64> {
+    let speed = speed.filter(|s| *s > 0.0)?;
+    let remaining_s = total_ms.saturating_sub(pos_ms) as f64 / 1000.0;
+    Some((remaining_s / speed).round() as u64)
+}
+
+/// Final size extrapolated from the bytes written so far.
+pub fn projected_size(written: u64, pos_ms: u64, total_ms: u64) -> Option<u64> {
+    if written == 0 || pos_ms == 0 {
+        return None;
+    }
+    Some((written as f64 * total_ms as f64 / pos_ms as f64) as u64)
+}
+
+pub fn format_hms(secs: u64) -> String {
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.2} {}", UNITS[unit])
+    }
+}
+
+/// When encode progress last moved, shared between the progress reader and
+/// the stall watchdog in `wait_for_completion`.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    started: Instant,
+    last_ms: Arc<AtomicU64>,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_ms: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn beat(&self) {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.last_ms.store(now, Ordering::Relaxed);
+    }
+
+    /// Time since the last beat (or since creation).
+    pub fn idle(&self) -> Duration {
+        let last = Duration::from_millis(self.last_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
     }
 }
 
 pub fn pump_progress<R: Read + Send + 'static>(
     reader: R,
     ui: ProgressUi,
+    heartbeat: Heartbeat,
 ) -> thread::JoinHandle<Result<()>> {
     thread::spawn(move || {
-        let re_kv = Regex::new(r"^(\w+)=([\w\-\.:]+)$").unwrap();
+        let re_kv = Regex::new(r"^(\w+)=(.*)$").unwrap();
         let reader = BufReader::new(reader);
+        let mut snap = ProgressSnapshot::default();
+        let mut last_seen = (0, 0, 0);
+        let mut ended = false;
 
         for line in reader.lines() {
             let line = line?;
-            if let Some(caps) = re_kv.captures(&line) {
+            if let Some(caps) = re_kv.captures(line.trim()) {
                 let key = &caps[1];
                 let val = &caps[2];
                 match key {
-                    "out_time_ms" => {
-                        let ms: u64 = val.parse().unwrap_or(0);
-                        let pos_ms = (ms / 1000).min(ui.total_ms);
-                        ui.update_stage(pos_ms);
-                    }
-                    "progress" if val == "end" => {
-                        ui.finish();
+                    // Each block ends with progress=continue|end.
+                    "progress" => {
+                        // Any of these moving means ffmpeg is not stuck.
+                        let seen = (snap.out_time_us, snap.frame, snap.total_size);
+                        if seen != last_seen {
+                            last_seen = seen;
+                            heartbeat.beat();
+                        }
+                        ui.update(&snap);
+                        if val == "end" {
+                            ended = true;
+                            ui.finish();
+                        }
                     }
-                    _ => {}
+                    _ => snap.apply(key, val),
                 }
             }
         }
+        if cancel::requested() {
+            ui.abandon("Cancelled");
+        } else if !ended {
+            ui.abandon("Stopped");
+        }
         Ok(())
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: &str = "frame=240
+fps=59.8
+stream_0_0_q=23.0
+bitrate=2145.3kbits/s
+total_size=2621440
+out_time_us=10000000
+out_time_ms=10000000
+out_time=00:00:10.000000
+dup_frames=1
+drop_frames=3
+speed=2.49x";
+
+    #[test]
+    fn test_snapshot_parses_progress_block() {
+        let mut snap = ProgressSnapshot::default();
+        for line in BLOCK.lines() {
+            let (k, v) = line.split_once('=').unwrap();
+            snap.apply(k, v);
+        }
+        assert_eq!(snap.frame, 240);
+        assert_eq!(snap.fps, 59.8);
+        assert_eq!(snap.bitrate_kbps, Some(2145.3));
+        assert_eq!(snap.total_size, 2_621_440);
+        assert_eq!(snap.speed, Some(2.49));
+        assert_eq!((snap.dup_frames, snap.drop_frames), (1, 3));
+        assert_eq!(snap.out_time_ms(), 10_000);
+
+        snap.apply("bitrate", "N/A");
+        snap.apply("speed", "N/A");
+        assert_eq!(snap.bitrate_kbps, None);
+        assert_eq!(snap.speed, None);
+    }
+
+    #[test]
+    fn test_eta_uses_encode_speed() {
+        // 50s of media left at 2x -> 25s.
+        assert_eq!(eta_seconds(60_000, 10_000, Some(2.0)), Some(25));
+        assert_eq!(eta_seconds(60_000, 10_000, None), None);
+        assert_eq!(eta_seconds(60_000, 10_000, Some(0.0)), None);
+    }
+
+    #[test]
+    fn test_projected_size() {
+        assert_eq!(projected_size(1_000, 15_000, 60_000), Some(4_000));
+        assert_eq!(projected_size(0, 15_000, 60_000), None);
+    }
+
+    #[test]
+    fn test_progress_event_is_flat_ndjson() {
+        let snap = ProgressSnapshot {
+            frame: 10,
+            speed: Some(2.0),
+            ..Default::default()
+        };
+        let event = Event::Progress {
+            snapshot: &snap,
+            out_time_ms: 500,
+            total_ms: 1000,
+            percent: 50.0,
+            eta_s: Some(1),
+            projected_size: None,
+        };
+        let line = serde_json::to_string(&event).unwrap();
+        assert!(line.starts_with(r#"{"event":"progress","frame":10,"#));
+        assert!(line.contains(r#""speed":2.0"#));
+        assert!(line.contains(r#""percent":50.0"#));
+        assert!(!line.contains('\n'));
+
+        let line = serde_json::to_string(&Event::Pass { pass: 1, of: 2 }).unwrap();
+        assert_eq!(line, r#"{"event":"pass","pass":1,"of":2}"#);
+    }
+
+    #[test]
+    fn test_format_hms() {
+        assert_eq!(format_hms(3725), "01:02:05");
+    }
+
+    #[test]
+    fn test_human_bytes() {
+        assert_eq!(human_bytes(512), "512 B");
+        assert_eq!(human_bytes(25 * 1024 * 1024), "25.00 MiB");
+    }
+
+    #[test]
+    fn test_heartbeat_idle_resets_on_beat() {
+        let hb = Heartbeat::new();
+        thread::sleep(Duration::from_millis(30));
+        assert!(hb.idle() >= Duration::from_millis(30));
+        hb.clone().beat();
+        assert!(hb.idle() < Duration::from_millis(30));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::cli::AppConfig;
+use crate::ffmpeg::{self, TempFile, Tools};
+use crate::graph::FilterGraph;
+use crate::progress;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Best quality the CRF search will try.
+pub const CRF_SEARCH_MIN: u8 = 12;
+/// Lowest quality the CRF search will try.
+pub const CRF_SEARCH_MAX: u8 = 36;
+pub const SAMPLE_COUNT: usize = 3;
+pub const SAMPLE_SECONDS: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityTarget {
+    /// Minimum SSIM "All" score (0..1).
+    Ssim(f64),
+    /// Minimum average PSNR in dB.
+    Psnr(f64),
+}
+
+impl QualityTarget {
+    pub fn threshold(self) -> f64 {
+        match self {
+            QualityTarget::Ssim(v) | QualityTarget::Psnr(v) => v,
+        }
+    }
+
+    /// ffmpeg filter that compares `[main][ref]`.
+    pub fn filter_name(self) -> &'static str {
+        match self {
+            QualityTarget::Ssim(_) => "ssim",
+            QualityTarget::Psnr(_) => "psnr",
+        }
+    }
+
+    /// Pulls the overall score from the filter's summary log line.
+    pub fn parse_score(self, log: &str) -> Option<f64> {
+        match self {
+            QualityTarget::Ssim(_) => parse_field_after(log, "SSIM ", "All:"),
+            QualityTarget::Psnr(_) => parse_field_after(log, "PSNR ", "average:"),
+        }
+    }
+}
+
+impl fmt::Display for QualityTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QualityTarget::Ssim(v) => write!(f, "SSIM {v}"),
+            QualityTarget::Psnr(v) => write!(f, "PSNR {v} dB"),
+        }
+    }
+}
+
+/// Reads `<field><number>` from the last log line containing `marker`.
+fn parse_field_after(log: &str, marker: &str, field: &str) -> Option<f64> {
+    let line = log.lines().rev().find(|l| l.contains(marker))?;
+    let rest = line.split(field).nth(1)?;
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+/// `(start, length)` windows in seconds spread evenly across the source.
+/// Short clips get one window covering everything.
+pub fn sample_windows(duration: f64, count: usize, seconds: f64) -> Vec<(f64, f64)> {
+    if duration <= seconds * count as f64 {
+        return vec![(0.0, duration)];
+    }
+    (0..count)
+        .map(|i| {
+            let center = duration * (i + 1) as f64 / (count + 1) as f64;
+            ((center - seconds / 2.0).max(0.0), seconds)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrfChoice {
+    pub crf: u8,
+    pub score: f64,
+    /// False when even `CRF_SEARCH_MIN` missed the target.
+    pub met: bool,
+}
+
+/// Binary-searches `[lo, hi]` for the highest CRF whose score still reaches
+/// `target`, assuming quality falls as CRF rises.
+pub fn search_crf<F>(lo: u8, hi: u8, target: f64, mut measure: F) -> Result<CrfChoice>
+where
+    F: FnMut(u8) -> Result<f64>,
+{
+    let mut scores: BTreeMap<u8, f64> = BTreeMap::new();
+    let mut best: Option<u8> = None;
+    let (mut low, mut high) = (lo as i32, hi as i32);
+
+    while low <= high {
+        let mid = ((low + high) / 2) as u8;
+        let score = measure(mid)?;
+        scores.insert(mid, score);
+        if score >= target {
+            best = Some(mid);
+            low = mid as i32 + 1;
+        } else {
+            high = mid as i32 - 1;
+        }
+    }
+
+    Ok(match best {
+        Some(crf) => CrfChoice {
+            crf,
+            score: scores[&crf],
+            met: true,
+        },
+        None => {
+            let score = match scores.get(&lo) {
+                Some(s) => *s,
+                None => measure(lo)?,
+            };
+            CrfChoice {
+                crf: lo,
+                score,
+                met: false,
+            }
+        }
+    })
+}
+
+/// Encodes a few sample windows at candidate CRFs, scores each against the
+/// filtered source and returns the highest CRF that meets `target`.
+/// `video_filters` must be per-frame only (no retiming), so samples line up.
+pub fn run_crf_search(
+    tools: &Tools,
+    cfg: &AppConfig,
+    duration: f64,
+    video_filters: &FilterGraph,
+    target: QualityTarget,
+) -> Result<CrfChoice> {
+    let windows = sample_windows(duration, SAMPLE_COUNT, SAMPLE_SECONDS);
+    let samples: Vec<TempFile> = (0..windows.len())
+        .map(|i| TempFile::new(&format!("sample{i}.mkv")))
+        .collect();
+
+    progress::note(
+        cfg.progress_format,
+        &format!(
+            "Searching CRF {CRF_SEARCH_MIN}..{CRF_SEARCH_MAX} for {target} on {} sample(s)",
+            windows.len()
+        ),
+    );
+    search_crf(CRF_SEARCH_MIN, CRF_SEARCH_MAX, target.threshold(), |crf| {
+        let mut worst = f64::INFINITY;
+        for (window, sample) in windows.iter().zip(&samples) {
+            ffmpeg::encode_sample(tools, cfg, *window, video_filters, crf, &sample.path)?;
+            let score = ffmpeg::measure_sample(
+                tools,
+                &cfg.input,
+                *window,
+                video_filters,
+                &sample.path,
+                target,
+            )?;
+            worst = worst.min(score);
+        }
+        progress::note(
+            cfg.progress_format,
+            &format!("  CRF {crf:>2} -> {} {worst:.4}", target.filter_name()),
+        );
+        Ok(worst)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scores() {
+        let ssim = "[Parsed_ssim_1 @ 0x5] SSIM Y:0.990 (20.0) U:0.995 (23.0) V:0.994 (22.2) All:0.991842 (20.884)";
+        assert_eq!(QualityTarget::Ssim(0.98).parse_score(ssim), Some(0.991842));
+
+        let psnr =
+            "[Parsed_psnr_1 @ 0x5] PSNR y:41.2 u:45.0 v:44.9 average:42.318 min:38.1 max:47.0";
+        assert_eq!(QualityTarget::Psnr(40.0).parse_score(psnr), Some(42.318));
+        assert_eq!(QualityTarget::Psnr(40.0).parse_score(ssim), None);
+    }
+
+    #[test]
+    fn test_sample_windows() {
+        assert_eq!(sample_windows(8.0, 3, 4.0), vec![(0.0, 8.0)]);
+        assert_eq!(
+            sample_windows(100.0, 3, 4.0),
+            vec![(23.0, 4.0), (48.0, 4.0), (73.0, 4.0)]
+        );
+    }
+
+    #[test]
+    fn test_search_crf_finds_highest_passing() {
+        // Score drops 0.005 per CRF step from 1.0 at CRF 12: 0.9799 is met up to CRF 16.
+        let model = |crf: u8| Ok(1.0 - (crf - 12) as f64 * 0.005);
+        let choice = search_crf(12, 36, 0.9799, model).unwrap();
+        assert_eq!(choice.crf, 16);
+        assert!(choice.met);
+    }
+
+    #[test]
+    fn test_search_crf_unreachable_target() {
+        let choice = search_crf(12, 36, 0.999, |_| Ok(0.95)).unwrap();
+        assert_eq!(choice.crf, 12);
+        assert!(!choice.met);
+    }
+}
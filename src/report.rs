@@ -0,0 +1,350 @@
+use crate::cli::AppConfig;
+use crate::ffmpeg::{self, TempFile, Tools};
+use crate::graph::FilterNode;
+use crate::progress::{self, human_bytes};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// How many of the lowest-scoring frames each metric lists.
+pub const WORST_FRAMES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Psnr,
+    Ssim,
+    Vmaf,
+}
+
+impl Metric {
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Psnr => "PSNR",
+            Metric::Ssim => "SSIM",
+            Metric::Vmaf => "VMAF",
+        }
+    }
+
+    /// Filter that compares `[main][ref]` and writes per-frame scores to `log`.
+    pub fn filter(self, log: &Path) -> FilterNode {
+        let log = log.to_string_lossy();
+        match self {
+            Metric::Psnr => FilterNode::new("psnr").opt("stats_file", log),
+            Metric::Ssim => FilterNode::new("ssim").opt("stats_file", log),
+            Metric::Vmaf => FilterNode::new("libvmaf")
+                .opt("log_fmt", "csv")
+                .opt("log_path", log),
+        }
+    }
+
+    /// Parses the per-frame log this metric's filter wrote.
+    pub fn parse_log(self, text: &str) -> Vec<FrameScore> {
+        match self {
+            Metric::Psnr => parse_stats_lines(text, "psnr_avg:"),
+            Metric::Ssim => parse_stats_lines(text, "All:"),
+            Metric::Vmaf => parse_vmaf_csv(text),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameScore {
+    pub frame: u64,
+    pub score: f64,
+}
+
+/// Scores of identical frames (infinite PSNR) are written as `"inf"`, since
+/// JSON has no infinity.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricSummary {
+    pub metric: &'static str,
+    #[serde(serialize_with = "serialize_score")]
+    pub average: f64,
+    #[serde(serialize_with = "serialize_score")]
+    pub min: f64,
+    pub frames: usize,
+    pub worst_frames: Vec<FrameScore>,
+}
+
+impl MetricSummary {
+    /// Infinite PSNR (identical frames) is left out of the average and minimum.
+    pub fn from_frames(metric: Metric, frames: &[FrameScore]) -> Option<Self> {
+        let finite: Vec<&FrameScore> = frames.iter().filter(|f| f.score.is_finite()).collect();
+        if frames.is_empty() {
+            return None;
+        }
+        let (average, min) = if finite.is_empty() {
+            (f64::INFINITY, f64::INFINITY)
+        } else {
+            let sum: f64 = finite.iter().map(|f| f.score).sum();
+            let min = finite.iter().map(|f| f.score).fold(f64::INFINITY, f64::min);
+            (sum / finite.len() as f64, min)
+        };
+        let mut worst: Vec<FrameScore> = finite.into_iter().cloned().collect();
+        worst.sort_by(|a, b| a.score.total_cmp(&b.score));
+        worst.truncate(WORST_FRAMES);
+        Some(Self {
+            metric: metric.name(),
+            average,
+            min,
+            frames: frames.len(),
+            worst_frames: worst,
+        })
+    }
+}
+
+fn serialize_score<S: serde::Serializer>(score: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    if score.is_finite() {
+        serializer.serialize_f64(*score)
+    } else {
+        serializer.serialize_str("inf")
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QualityReport<'a> {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub metrics: Vec<MetricSummary>,
+    pub settings: &'a AppConfig,
+}
+
+impl QualityReport<'_> {
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let _ = writeln!(md, "# Quality report\n");
+        let _ = writeln!(md, "| | Path | Size |");
+        let _ = writeln!(md, "|---|---|---|");
+        let _ = writeln!(
+            md,
+            "| Source | `{}` | {} |",
+            self.input.display(),
+            human_bytes(self.input_bytes)
+        );
+        let _ = writeln!(
+            md,
+            "| Output | `{}` | {} |",
+            self.output.display(),
+            human_bytes(self.output_bytes)
+        );
+
+        let _ = writeln!(md, "\n## Metrics\n");
+        let _ = writeln!(md, "| Metric | Average | Min | Frames |");
+        let _ = writeln!(md, "|---|---|---|---|");
+        for m in &self.metrics {
+            let _ = writeln!(
+                md,
+                "| {} | {:.4} | {:.4} | {} |",
+                m.metric, m.average, m.min, m.frames
+            );
+        }
+        for m in &self.metrics {
+            let _ = writeln!(md, "\n### Worst {} frames\n", m.metric);
+            let _ = writeln!(md, "| Frame | Score |");
+            let _ = writeln!(md, "|---|---|");
+            for f in &m.worst_frames {
+                let _ = writeln!(md, "| {} | {:.4} |", f.frame, f.score);
+            }
+        }
+
+        let settings = serde_json::to_string_pretty(self.settings).unwrap_or_default();
+        let _ = writeln!(md, "\n## Settings\n\n```json\n{settings}\n```");
+        md
+    }
+
+    /// Markdown for `.md`/`.markdown` paths, JSON otherwise.
+    pub fn render_for(&self, path: &Path) -> Result<String> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        Ok(match ext.as_str() {
+            "md" | "markdown" => self.to_markdown(),
+            _ => serde_json::to_string_pretty(self)? + "\n",
+        })
+    }
+}
+
+/// Compares the finished output with `reference_graph` (the source retimed like
+/// the output, ending in `[vout]`) and writes the report to `path`.
+pub fn write_report(
+    tools: &Tools,
+    cfg: &AppConfig,
+    reference_graph: &str,
+    path: &Path,
+) -> Result<()> {
+    let mut metrics = vec![Metric::Psnr, Metric::Ssim];
+    if ffmpeg::has_filter(tools, "libvmaf") {
+        metrics.push(Metric::Vmaf);
+    }
+    let logs: Vec<TempFile> = metrics
+        .iter()
+        .map(|m| TempFile::new(&format!("{}.log", m.name().to_ascii_lowercase())))
+        .collect();
+    let filters: Vec<FilterNode> = metrics
+        .iter()
+        .zip(&logs)
+        .map(|(m, log)| m.filter(&log.path))
+        .collect();
+
+    progress::note(
+        cfg.progress_format,
+        &format!("Measuring {} against the source...", metric_names(&metrics)),
+    );
+    ffmpeg::compare_quality(tools, &cfg.input, &cfg.output, reference_graph, &filters)?;
+
+    let mut summaries = Vec::new();
+    for (metric, log) in metrics.iter().zip(&logs) {
+        let text = std::fs::read_to_string(&log.path)
+            .with_context(|| format!("missing {} log", metric.name()))?;
+        if let Some(summary) = MetricSummary::from_frames(*metric, &metric.parse_log(&text)) {
+            summaries.push(summary);
+        }
+    }
+
+    let report = QualityReport {
+        input: cfg.input.clone(),
+        output: cfg.output.clone(),
+        input_bytes: std::fs::metadata(&cfg.input)?.len(),
+        output_bytes: std::fs::metadata(&cfg.output)?.len(),
+        metrics: summaries,
+        settings: cfg,
+    };
+    std::fs::write(path, report.render_for(path)?)
+        .with_context(|| format!("cannot write report {}", path.display()))?;
+    progress::note(
+        cfg.progress_format,
+        &format!("Report written to {}", path.display()),
+    );
+    Ok(())
+}
+
+fn metric_names(metrics: &[Metric]) -> String {
+    metrics
+        .iter()
+        .map(|m| m.name())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// ssim/psnr stats lines look like `n:1 ... <field><value> ...`.
+fn parse_stats_lines(text: &str, field: &str) -> Vec<FrameScore> {
+    text.lines()
+        .filter_map(|line| {
+            let n = line.strip_prefix("n:")?.split_whitespace().next()?;
+            let value = line.split(field).nth(1)?.split_whitespace().next()?;
+            Some(FrameScore {
+                frame: n.parse::<u64>().ok()?.saturating_sub(1),
+                score: value.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// libvmaf CSV: header row with `Frame` and `vmaf` columns.
+fn parse_vmaf_csv(text: &str) -> Vec<FrameScore> {
+    let mut lines = text.lines();
+    let Some(header) = lines.next() else {
+        return Vec::new();
+    };
+    let cols: Vec<&str> = header.split(',').map(str::trim).collect();
+    let (Some(fi), Some(vi)) = (
+        cols.iter().position(|c| c.eq_ignore_ascii_case("frame")),
+        cols.iter().position(|c| *c == "vmaf"),
+    ) else {
+        return Vec::new();
+    };
+    lines
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            Some(FrameScore {
+                frame: fields.get(fi)?.parse().ok()?,
+                score: fields.get(vi)?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ssim_and_psnr_stats() {
+        let ssim =
+            "n:1 Y:0.99 U:0.99 V:0.99 All:0.985 (18.2)\nn:2 Y:0.9 U:0.9 V:0.9 All:0.900 (10.0)\n";
+        let frames = Metric::Ssim.parse_log(ssim);
+        assert_eq!(
+            frames[1],
+            FrameScore {
+                frame: 1,
+                score: 0.9
+            }
+        );
+
+        let psnr =
+            "n:1 mse_avg:1.2 mse_y:1.0 psnr_avg:47.3 psnr_y:48.0\nn:2 mse_avg:0.0 psnr_avg:inf\n";
+        let frames = Metric::Psnr.parse_log(psnr);
+        assert_eq!(frames.len(), 2);
+        assert!(frames[1].score.is_infinite());
+    }
+
+    #[test]
+    fn test_parse_vmaf_csv() {
+        let csv = "Frame,integer_motion,vmaf\n0,0.0,95.5\n1,1.2,88.25\n";
+        let frames = Metric::Vmaf.parse_log(csv);
+        assert_eq!(
+            frames,
+            vec![
+                FrameScore {
+                    frame: 0,
+                    score: 95.5
+                },
+                FrameScore {
+                    frame: 1,
+                    score: 88.25
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_summary_skips_infinite_psnr() {
+        let frames = vec![
+            FrameScore {
+                frame: 0,
+                score: f64::INFINITY,
+            },
+            FrameScore {
+                frame: 1,
+                score: 40.0,
+            },
+            FrameScore {
+                frame: 2,
+                score: 30.0,
+            },
+        ];
+        let s = MetricSummary::from_frames(Metric::Psnr, &frames).unwrap();
+        assert_eq!(s.average, 35.0);
+        assert_eq!(s.min, 30.0);
+        assert_eq!(s.frames, 3);
+        assert_eq!(s.worst_frames[0].frame, 2);
+        assert!(MetricSummary::from_frames(Metric::Psnr, &[]).is_none());
+    }
+
+    #[test]
+    fn test_identical_frames_serialize_as_inf() {
+        let frames = [FrameScore {
+            frame: 0,
+            score: f64::INFINITY,
+        }];
+        let s = MetricSummary::from_frames(Metric::Psnr, &frames).unwrap();
+        let json = serde_json::to_value(&s).unwrap();
+        assert_eq!(json["average"], "inf");
+        assert_eq!(json["min"], "inf");
+    }
+}
@@ -0,0 +1,335 @@
+use crate::graph::FilterNode;
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// swscale algorithm for `--scaler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scaler {
+    Bilinear,
+    Bicubic,
+    /// Sharpest for downscales.
+    Lanczos,
+    Spline,
+}
+
+impl Scaler {
+    fn flag(self) -> &'static str {
+        match self {
+            Scaler::Bilinear => "bilinear",
+            Scaler::Bicubic => "bicubic",
+            Scaler::Lanczos => "lanczos",
+            Scaler::Spline => "spline",
+        }
+    }
+}
+
+/// How the output size is derived from the source.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resize {
+    /// `--scale`: this height, width keeps the aspect ratio.
+    Height(u32),
+    /// `--width`: this width, height keeps the aspect ratio.
+    Width(u32),
+    /// `--scale-factor`.
+    Factor(f64),
+    /// `--fit`: the largest size inside the box that keeps the aspect ratio.
+    Fit { width: u32, height: u32 },
+}
+
+/// Frame size after scaling, once the source size is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct OutputSize {
+    /// Scaled picture; `None` leaves the source size.
+    pub scaled: Option<(u32, u32)>,
+    /// `--fit ... --pad`: picture centred on a frame of this size.
+    pub padded: Option<(u32, u32)>,
+    /// Final frame size.
+    pub frame: (u32, u32),
+}
+
+/// Scaling options, and after probing the resulting size.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ScaleSettings {
+    pub resize: Option<Resize>,
+    /// Pad `--fit` output to the full box.
+    pub pad: bool,
+    /// Never enlarge the source.
+    pub no_upscale: bool,
+    pub scaler: Option<Scaler>,
+    pub size: Option<OutputSize>,
+}
+
+impl ScaleSettings {
+    /// Predicts the output size for a `width`x`height` picture the way ffmpeg
+    /// evaluates `filters` on it. Dimensions come out even, as 4:2:0 chroma
+    /// requires.
+    pub fn resolve(&mut self, width: u32, height: u32) {
+        self.size = None;
+        let Some(resize) = self.resize else {
+            return;
+        };
+        if width == 0 || height == 0 {
+            return;
+        }
+        let (w, h) = (width as f64, height as f64);
+        let scaled = match resize {
+            Resize::Height(target) => {
+                let target = self.cap(target, height);
+                Some((even(target as f64 * w / h), target))
+            }
+            Resize::Width(target) => {
+                let target = self.cap(target, width);
+                Some((target, even(target as f64 * h / w)))
+            }
+            Resize::Factor(factor) if self.no_upscale && factor > 1.0 => None,
+            Resize::Factor(factor) => Some((floor_even(w * factor), floor_even(h * factor))),
+            Resize::Fit {
+                width: box_w,
+                height: box_h,
+            } => {
+                let (box_w, box_h) = (self.cap(box_w, width), self.cap(box_h, height));
+                let fit_w = (box_h as f64 * w / h).round().min(box_w as f64);
+                let fit_h = (box_w as f64 * h / w).round().min(box_h as f64);
+                Some((floor_even(fit_w), floor_even(fit_h)))
+            }
+        }
+        .filter(|&size| size != (width, height));
+        let padded = match resize {
+            Resize::Fit {
+                width: box_w,
+                height: box_h,
+            } if self.pad => Some((box_w, box_h)),
+            _ => None,
+        };
+        let frame = padded.or(scaled).unwrap_or((width, height));
+        if frame != (width, height) {
+            self.size = Some(OutputSize {
+                scaled,
+                padded,
+                frame,
+            });
+        }
+    }
+
+    /// Final frame size when it differs from the source.
+    pub fn frame(&self) -> Option<(u32, u32)> {
+        self.size.map(|s| s.frame)
+    }
+
+    /// `scale` (and `pad`) filters. Sizes are expressions ffmpeg evaluates
+    /// against the frames it actually gets, so auto-rotated phone clips and
+    /// `--vf-pre` crops keep their shape.
+    pub fn filters(&self) -> Vec<FilterNode> {
+        let Some(resize) = self.resize else {
+            return Vec::new();
+        };
+        let scale = FilterNode::new("scale");
+        let scale = match resize {
+            Resize::Height(h) => Some(scale.arg(-2).arg(self.cap_expr(h, "ih"))),
+            Resize::Width(w) => Some(scale.arg(self.cap_expr(w, "iw")).arg(-2)),
+            Resize::Factor(factor) if self.no_upscale && factor > 1.0 => None,
+            Resize::Factor(factor) => Some(
+                scale
+                    .arg(format!("trunc(iw*{factor}/2)*2"))
+                    .arg(format!("trunc(ih*{factor}/2)*2")),
+            ),
+            Resize::Fit { width, height } => Some(
+                scale
+                    .arg(self.cap_expr(width, "iw"))
+                    .arg(self.cap_expr(height, "ih"))
+                    .opt("force_original_aspect_ratio", "decrease")
+                    .opt("force_divisible_by", 2),
+            ),
+        };
+        let mut filters = Vec::new();
+        if let Some(mut scale) = scale {
+            if let Some(scaler) = self.scaler {
+                scale = scale.opt("flags", scaler.flag());
+            }
+            filters.push(scale);
+        }
+        if let Resize::Fit { width, height } = resize
+            && self.pad
+        {
+            filters.push(
+                FilterNode::new("pad")
+                    .arg(width)
+                    .arg(height)
+                    .arg("(ow-iw)/2")
+                    .arg("(oh-ih)/2"),
+            );
+        }
+        filters
+    }
+
+    /// `size`, or `source` when that is smaller and upscaling is off.
+    fn cap(&self, size: u32, source: u32) -> u32 {
+        if self.no_upscale {
+            size.min(source)
+        } else {
+            size
+        }
+    }
+
+    /// `cap` as an ffmpeg expression over the input size `input` (`iw`/`ih`).
+    fn cap_expr(&self, size: u32, input: &str) -> String {
+        if self.no_upscale {
+            format!("min({size},{input})")
+        } else {
+            size.to_string()
+        }
+    }
+}
+
+/// Nearest even number, at least 2; what ffmpeg makes of a `-2` size.
+pub(crate) fn even(x: f64) -> u32 {
+    ((x / 2.0).round() as u32).max(1) * 2
+}
+
+/// Even number at or below `x`, at least 2.
+pub(crate) fn floor_even(x: f64) -> u32 {
+    ((x / 2.0).floor() as u32).max(1) * 2
+}
+
+/// Parses `--width`: a positive even integer.
+pub fn validate_width(raw: &str) -> Result<u32, String> {
+    let parsed: u32 = raw
+        .parse()
+        .map_err(|_| format!("`{raw}` must be a positive even integer"))?;
+    if parsed == 0 || !parsed.is_multiple_of(2) {
+        return Err("width must be a positive even integer (e.g., 1280, 640)".into());
+    }
+    Ok(parsed)
+}
+
+/// Parses `--scale-factor`, e.g. `0.5`.
+pub fn validate_scale_factor(raw: &str) -> Result<f64, String> {
+    match raw.parse::<f64>() {
+        Ok(f) if f > 0.0 && f.is_finite() => Ok(f),
+        _ => Err(format!("`{raw}` must be a number greater than 0")),
+    }
+}
+
+/// Parses `--fit WxH`, e.g. `1920x1080`.
+pub fn parse_fit(raw: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("`{raw}` must be WIDTHxHEIGHT with even sizes, e.g. 1920x1080");
+    let (w, h) = raw.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+    let w: u32 = w.parse().map_err(|_| invalid())?;
+    let h: u32 = h.parse().map_err(|_| invalid())?;
+    if w == 0 || h == 0 || !w.is_multiple_of(2) || !h.is_multiple_of(2) {
+        return Err(invalid());
+    }
+    Ok((w, h))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved(resize: Resize, source: (u32, u32)) -> ScaleSettings {
+        let mut s = ScaleSettings {
+            resize: Some(resize),
+            ..Default::default()
+        };
+        s.resolve(source.0, source.1);
+        s
+    }
+
+    #[test]
+    fn test_sizes_keep_aspect_and_stay_even() {
+        assert_eq!(
+            resolved(Resize::Height(720), (1920, 1080)).frame(),
+            Some((1280, 720))
+        );
+        assert_eq!(
+            resolved(Resize::Width(640), (1920, 1080)).frame(),
+            Some((640, 360))
+        );
+        assert_eq!(
+            resolved(Resize::Factor(0.5), (1366, 768)).frame(),
+            Some((682, 384))
+        );
+        let fit = Resize::Fit {
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(resolved(fit, (1080, 1920)).frame(), Some((608, 1080)));
+        assert_eq!(resolved(Resize::Height(1080), (1920, 1080)).frame(), None);
+    }
+
+    #[test]
+    fn test_fit_with_padding_and_scaler() {
+        let mut s = ScaleSettings {
+            resize: Some(Resize::Fit {
+                width: 1920,
+                height: 1080,
+            }),
+            pad: true,
+            scaler: Some(Scaler::Lanczos),
+            ..Default::default()
+        };
+        let filters: Vec<String> = s.filters().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            filters,
+            [
+                "scale=1920:1080:force_original_aspect_ratio=decrease:force_divisible_by=2:\
+                 flags=lanczos",
+                "pad=1920:1080:(ow-iw)/2:(oh-ih)/2"
+            ]
+        );
+        s.resolve(2880, 2160);
+        assert_eq!(
+            s.size,
+            Some(OutputSize {
+                scaled: Some((1440, 1080)),
+                padded: Some((1920, 1080)),
+                frame: (1920, 1080),
+            })
+        );
+    }
+
+    #[test]
+    fn test_sizes_are_expressions() {
+        let filter = |resize| {
+            ScaleSettings {
+                resize: Some(resize),
+                ..Default::default()
+            }
+            .filters()[0]
+                .to_string()
+        };
+        assert_eq!(filter(Resize::Height(720)), "scale=-2:720");
+        assert_eq!(filter(Resize::Width(640)), "scale=640:-2");
+        assert_eq!(
+            filter(Resize::Factor(0.5)),
+            "scale=trunc(iw*0.5/2)*2:trunc(ih*0.5/2)*2"
+        );
+    }
+
+    #[test]
+    fn test_no_upscale_keeps_small_sources() {
+        let mut s = ScaleSettings {
+            resize: Some(Resize::Height(1080)),
+            no_upscale: true,
+            ..Default::default()
+        };
+        assert_eq!(s.filters()[0].to_string(), "scale=-2:min(1080\\,ih)");
+        s.resolve(640, 480);
+        assert_eq!(s.size, None);
+        s.resolve(3840, 2160);
+        assert_eq!(s.frame(), Some((1920, 1080)));
+
+        s.resize = Some(Resize::Factor(2.0));
+        assert!(s.filters().is_empty());
+    }
+
+    #[test]
+    fn test_parse_fit() {
+        assert_eq!(parse_fit("1920x1080"), Ok((1920, 1080)));
+        assert!(parse_fit("1920").is_err());
+        assert!(parse_fit("1921x1080").is_err());
+        assert!(validate_scale_factor("0").is_err());
+    }
+}
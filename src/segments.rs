@@ -0,0 +1,242 @@
+use serde::Serialize;
+
+/// Segments shorter than this (seconds) are folded into their neighbours.
+pub const MIN_SEGMENT_SECONDS: f64 = 0.05;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VariableSpeed {
+    pub speech: f64,
+    pub silence: f64,
+    pub threshold_db: f64,
+    pub min_silence: f64,
+}
+
+/// Speed that applies from `at` seconds until the next keyframe.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpeedKeyframe {
+    pub at: f64,
+    pub speed: f64,
+}
+
+/// A slice of the source `[start, end)` in seconds, played back at `speed`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub start: f64,
+    pub end: f64,
+    pub speed: f64,
+}
+
+impl Segment {
+    pub fn output_seconds(&self) -> f64 {
+        (self.end - self.start) / self.speed
+    }
+}
+
+/// Parses `silence_start` / `silence_end` pairs from silencedetect's log output.
+/// A silence still open at end of stream is closed at `duration`.
+pub fn parse_silencedetect(log: &str, duration: f64) -> Vec<(f64, f64)> {
+    let mut silences = Vec::new();
+    let mut open: Option<f64> = None;
+
+    for line in log.lines() {
+        if let Some(rest) = line.split("silence_start:").nth(1) {
+            if let Ok(t) = rest.trim().parse::<f64>() {
+                open = Some(t.max(0.0));
+            }
+        } else if let Some(rest) = line.split("silence_end:").nth(1) {
+            let raw = rest.split('|').next().unwrap_or("").trim();
+            if let (Some(start), Ok(end)) = (open.take(), raw.parse::<f64>()) {
+                silences.push((start, end.min(duration)));
+            }
+        }
+    }
+    if let Some(start) = open {
+        silences.push((start, duration));
+    }
+    silences
+}
+
+/// Splits `[0, duration)` into alternating speech/silence segments.
+pub fn segments_from_silence(
+    duration: f64,
+    silences: &[(f64, f64)],
+    speech_speed: f64,
+    silence_speed: f64,
+) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut cursor = 0.0;
+    for &(start, end) in silences {
+        let start = start.clamp(cursor, duration);
+        let end = end.clamp(start, duration);
+        push_segment(&mut segments, cursor, start, speech_speed);
+        push_segment(&mut segments, start, end, silence_speed);
+        cursor = end;
+    }
+    push_segment(&mut segments, cursor, duration, speech_speed);
+    segments
+}
+
+/// Parses `"0:1.0,30:4.0,45:1.0"` (seconds:speed). Entries may also be split by
+/// newlines, and `#` starts a comment, so the same syntax works for ramp files.
+pub fn parse_speed_map(raw: &str) -> Result<Vec<SpeedKeyframe>, String> {
+    let mut keyframes: Vec<SpeedKeyframe> = Vec::new();
+    for line in raw.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        for entry in line.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (at, speed) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("`{entry}` must look like <seconds>:<speed>"))?;
+            let at: f64 = at
+                .trim()
+                .parse()
+                .map_err(|_| format!("`{at}` is not a time in seconds"))?;
+            let speed: f64 = speed
+                .trim()
+                .parse()
+                .map_err(|_| format!("`{speed}` is not a speed factor"))?;
+            if !at.is_finite() || at < 0.0 {
+                return Err(format!("keyframe time {at} must be >= 0"));
+            }
+            if !speed.is_finite() || speed <= 0.0 {
+                return Err(format!("speed at {at}s must be a finite number > 0.0"));
+            }
+            if let Some(prev) = keyframes.last()
+                && at <= prev.at
+            {
+                return Err(format!(
+                    "keyframe times must increase ({} then {at})",
+                    prev.at
+                ));
+            }
+            keyframes.push(SpeedKeyframe { at, speed });
+        }
+    }
+    if keyframes.is_empty() {
+        return Err("speed map has no keyframes".into());
+    }
+    Ok(keyframes)
+}
+
+/// Turns keyframes into constant-speed segments. Anything before the first
+/// keyframe plays at `base_speed`; keyframes past `duration` are ignored.
+pub fn segments_from_keyframes(
+    duration: f64,
+    keyframes: &[SpeedKeyframe],
+    base_speed: f64,
+) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut cursor = 0.0;
+    let mut speed = base_speed;
+    for kf in keyframes {
+        let at = kf.at.min(duration);
+        push_segment(&mut segments, cursor, at, speed);
+        cursor = at;
+        speed = kf.speed;
+    }
+    push_segment(&mut segments, cursor, duration, speed);
+    segments
+}
+
+/// Appends `[start, end)` at `speed`, merging with the previous segment when the
+/// speed matches or the new piece is too short to be worth its own trim.
+pub fn push_segment(segments: &mut Vec<Segment>, start: f64, end: f64, speed: f64) {
+    if end - start <= 0.0 {
+        return;
+    }
+    if let Some(last) = segments.last_mut()
+        && ((last.speed - speed).abs() < 1e-6 || end - start < MIN_SEGMENT_SECONDS)
+    {
+        last.end = end;
+        return;
+    }
+    segments.push(Segment { start, end, speed });
+}
+
+/// Output duration of the whole plan, used for progress and ETA.
+pub fn planned_duration_ms(segments: &[Segment]) -> u64 {
+    let seconds: f64 = segments.iter().map(Segment::output_seconds).sum();
+    (seconds * 1000.0).max(1.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+[silencedetect @ 0x1] silence_start: 2.5
+[silencedetect @ 0x1] silence_end: 4 | silence_duration: 1.5
+[silencedetect @ 0x1] silence_start: 9.25
+";
+
+    #[test]
+    fn test_parse_silencedetect() {
+        let s = parse_silencedetect(LOG, 10.0);
+        assert_eq!(s, vec![(2.5, 4.0), (9.25, 10.0)]);
+    }
+
+    #[test]
+    fn test_segments_from_silence() {
+        let segs = segments_from_silence(10.0, &[(2.5, 4.0), (9.25, 10.0)], 1.5, 4.0);
+        let spans: Vec<_> = segs.iter().map(|s| (s.start, s.end, s.speed)).collect();
+        assert_eq!(
+            spans,
+            vec![
+                (0.0, 2.5, 1.5),
+                (2.5, 4.0, 4.0),
+                (4.0, 9.25, 1.5),
+                (9.25, 10.0, 4.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_planned_duration_sums_segments() {
+        let segs = segments_from_silence(10.0, &[(2.0, 6.0)], 1.0, 4.0);
+        // 2s + 4s/4 + 4s = 7s
+        assert_eq!(planned_duration_ms(&segs), 7000);
+    }
+
+    #[test]
+    fn test_parse_speed_map() {
+        let kf = parse_speed_map("0:1.0, 30:4.0,45:1").unwrap();
+        assert_eq!(kf.len(), 3);
+        assert_eq!(
+            kf[1],
+            SpeedKeyframe {
+                at: 30.0,
+                speed: 4.0
+            }
+        );
+
+        let file = "# ramp\n0:1.0\n30:4.0 # fast\n45:1.0\n";
+        assert_eq!(parse_speed_map(file).unwrap(), kf);
+
+        assert!(parse_speed_map("30:4.0,10:1.0").is_err());
+        assert!(parse_speed_map("0:0").is_err());
+        assert!(parse_speed_map("0:inf").is_err());
+        assert!(parse_speed_map("0:nan").is_err());
+        assert!(parse_speed_map("inf:2").is_err());
+        assert!(parse_speed_map("fast").is_err());
+        assert!(parse_speed_map("").is_err());
+    }
+
+    #[test]
+    fn test_segments_from_keyframes() {
+        let kf = parse_speed_map("10:1.0,30:4.0,45:1.0,90:2.0").unwrap();
+        let segs = segments_from_keyframes(60.0, &kf, 1.0);
+        let spans: Vec<_> = segs.iter().map(|s| (s.start, s.end, s.speed)).collect();
+        assert_eq!(
+            spans,
+            vec![(0.0, 30.0, 1.0), (30.0, 45.0, 4.0), (45.0, 60.0, 1.0)]
+        );
+        // 30s + 15s/4 + 15s
+        assert_eq!(planned_duration_ms(&segs), 48_750);
+    }
+
+    #[test]
+    fn test_short_segments_are_merged() {
+        let segs = segments_from_silence(10.0, &[(5.0, 5.01)], 1.0, 4.0);
+        assert_eq!(segs.len(), 1);
+        assert_eq!(segs[0].end, 10.0);
+    }
+}
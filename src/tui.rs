@@ -1,5 +1,11 @@
-use crate::cli::{default_output, AppConfig};
-use crate::filters::validate_percent_range;
+use crate::aspect::AspectSettings;
+use crate::cli::{default_output, parse_byte_size, AppConfig, ExistingOutput};
+use crate::color::ColorSettings;
+use crate::encoder::EncoderSettings;
+use crate::filters::{validate_percent_range, Direction, DEFAULT_REVERSE_MEMORY_LIMIT};
+use crate::framerate::FrameRateSettings;
+use crate::progress::ProgressFormat;
+use crate::scale::{Resize, ScaleSettings};
 use anyhow::{bail, Result};
 use dialoguer::{theme::ColorfulTheme, Confirm, Input};
 use std::path::PathBuf;
@@ -29,7 +35,7 @@ pub fn interactive_config() -> Result<AppConfig> {
         bail!("Speed must be > 0.0");
     }
 
-    let default_out = default_output(&input, speed);
+    let default_out = default_output(&input, &format!("speed{speed}"));
     let out_prompt = format!(
         "Output file path [{}]",
         default_out.as_os_str().to_string_lossy()
@@ -77,15 +83,43 @@ pub fn interactive_config() -> Result<AppConfig> {
         input,
         output,
         speed,
+        variable_speed: None,
+        speed_map: None,
+        direction: Direction::Forward,
+        reverse_memory_limit: parse_byte_size(DEFAULT_REVERSE_MEMORY_LIMIT)
+            .expect("default reverse memory limit is valid"),
+        target_size: None,
+        quality_target: None,
         crf,
+        rate_control: None,
         preset,
+        encoder: EncoderSettings::default(),
         denoise,
-        scale: scale_height,
+        scale: ScaleSettings {
+            resize: scale_height.map(Resize::Height),
+            ..Default::default()
+        },
+        aspect: AspectSettings::default(),
+        frame_rate: FrameRateSettings::default(),
+        color: ColorSettings::default(),
         sharpen,
         contrast,
         saturation,
         brightness,
+        vf_pre: None,
+        vf_post: None,
+        af_pre: None,
+        af_post: None,
+        report: None,
+        dry_run: false,
+        print_command: false,
+        fallback: false,
+        verify_decode: false,
+        existing: ExistingOutput::Overwrite,
+        keep_partial: false,
+        progress_format: ProgressFormat::Bar,
         verbose,
+        stall_timeout: None,
         threads,
         ffmpeg: ffmpeg_path,
         ffprobe: ffprobe_path,
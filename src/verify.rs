@@ -0,0 +1,132 @@
+use crate::error::{EnhancerError, STDERR_SHOWN_LINES};
+use crate::ffmpeg::{self, Tools};
+use crate::probe::MediaInfo;
+use anyhow::Result;
+use std::path::Path;
+
+/// Allowed duration mismatch: the larger of this and `DURATION_TOLERANCE_RATIO`.
+/// Retiming rounds to whole frames and audio packets, so exact matches are rare.
+pub const DURATION_TOLERANCE_MS: u64 = 500;
+pub const DURATION_TOLERANCE_RATIO: f64 = 0.01;
+
+/// What a finished encode should look like.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expected {
+    pub duration_ms: u64,
+    pub video: bool,
+    pub audio: bool,
+    /// Output frame size when scaled.
+    pub size: Option<(u32, u32)>,
+}
+
+impl Expected {
+    pub fn tolerance_ms(&self) -> u64 {
+        DURATION_TOLERANCE_MS.max((self.duration_ms as f64 * DURATION_TOLERANCE_RATIO) as u64)
+    }
+
+    /// Every way `actual` falls short; empty when it matches.
+    pub fn problems(&self, actual: &MediaInfo) -> Vec<String> {
+        let mut problems = Vec::new();
+        let actual_ms = (actual.duration * 1000.0).round() as u64;
+        if actual_ms.abs_diff(self.duration_ms) > self.tolerance_ms() {
+            problems.push(format!(
+                "duration is {:.2}s, expected {:.2}s",
+                actual_ms as f64 / 1000.0,
+                self.duration_ms as f64 / 1000.0
+            ));
+        }
+        match &actual.video {
+            None if self.video => problems.push("video stream is missing".to_string()),
+            Some(v) => {
+                if let Some((w, h)) = self.size
+                    && (v.width, v.height) != (w, h)
+                {
+                    problems.push(format!(
+                        "resolution is {}x{}, expected {w}x{h}",
+                        v.width, v.height
+                    ));
+                }
+            }
+            None => {}
+        }
+        if self.audio && actual.audio.is_none() {
+            problems.push("audio stream is missing".to_string());
+        }
+        problems
+    }
+}
+
+/// Probes `path` and, with `full_decode`, decodes every frame. Any mismatch
+/// is an `EnhancerError::Verification`.
+pub fn verify_output(
+    tools: &Tools,
+    path: &Path,
+    expected: &Expected,
+    full_decode: bool,
+) -> Result<()> {
+    let info = ffmpeg::probe_media(tools, path)
+        .map_err(|e| EnhancerError::Verification(format!("cannot probe output: {e:#}")))?;
+    let mut problems = expected.problems(&info);
+    if full_decode {
+        let errors = ffmpeg::decode_errors(tools, path)?;
+        if !errors.is_empty() {
+            let shown = &errors[errors.len().saturating_sub(STDERR_SHOWN_LINES)..];
+            problems.push(format!(
+                "{} decode error(s), last: {}",
+                errors.len(),
+                shown.join(" | ")
+            ));
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(EnhancerError::Verification(problems.join("; ")).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::{AudioInfo, VideoInfo};
+
+    fn media(duration: f64, height: u32, audio: bool) -> MediaInfo {
+        MediaInfo {
+            duration,
+            video: Some(VideoInfo {
+                width: height * 16 / 9,
+                height,
+                avg_frame_rate: 30.0,
+                ..Default::default()
+            }),
+            audio: audio.then(AudioInfo::default),
+        }
+    }
+
+    #[test]
+    fn test_matching_output_passes() {
+        let expected = Expected {
+            duration_ms: 60_000,
+            video: true,
+            audio: true,
+            size: Some((1280, 720)),
+        };
+        // 1% of 60 s = 600 ms of slack.
+        assert!(expected.problems(&media(60.55, 720, true)).is_empty());
+    }
+
+    #[test]
+    fn test_reports_each_mismatch() {
+        let expected = Expected {
+            duration_ms: 10_000,
+            video: true,
+            audio: true,
+            size: Some((1280, 720)),
+        };
+        let problems = expected.problems(&media(4.0, 1080, false));
+        assert_eq!(problems.len(), 3);
+        assert_eq!(problems[0], "duration is 4.00s, expected 10.00s");
+        assert!(problems[1].starts_with("resolution is 1920x1080"));
+        assert_eq!(problems[2], "audio stream is missing");
+    }
+}